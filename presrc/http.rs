@@ -0,0 +1,411 @@
+//#if USE_CURL_LIB
+use curl::easy::{Easy2, Handler, HttpVersion, List, WriteError};
+use curl::multi::Multi;
+use curl::Version;
+//#endif
+use github::GitHub;
+use error::Error;
+use std::cell::RefCell;
+use std::cmp;
+use std::mem;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Longest the client will sleep for a rate limit before giving up.
+const MAX_WAIT: u64 = 300;
+
+/// Most redirects followed for one request.
+const MAX_REDIRECTS: u32 = 5;
+
+//#if USE_CURL_LIB
+impl From<curl::Error> for Error {
+    fn from(err: curl::Error) -> Error {
+        Error::Network(err.to_string())
+    }
+}
+
+impl From<curl::MultiError> for Error {
+    fn from(err: curl::MultiError) -> Error {
+        Error::Network(err.to_string())
+    }
+}
+
+pub struct Collector {
+    data: Vec<u8>,
+    headers: Vec<(String, String)>,
+}
+
+impl Collector {
+    fn new() -> Collector {
+        Collector {
+            data: Vec::new(),
+            headers: Vec::new(),
+        }
+    }
+}
+
+impl Handler for Collector {
+    fn write(&mut self, data: &[u8]) -> Result<usize, WriteError> {
+        self.data.extend_from_slice(data);
+        Ok(data.len())
+    }
+    fn header(&mut self, data: &[u8]) -> bool {
+        let line = String::from_utf8_lossy(data);
+        if line.starts_with("HTTP/") {
+            self.headers.clear();
+        } else if let Some(i) = line.find(':') {
+            self.headers.push((line[..i].trim().to_lowercase(), line[i + 1..].trim().to_owned()));
+        }
+        true
+    }
+}
+//#endif
+
+struct Response {
+    code: u32,
+    data: Vec<u8>,
+    headers: Vec<(String, String)>,
+}
+
+impl Response {
+    fn get_header(&self, name: &str) -> Option<u64> {
+        self.headers.iter().find(|h| h.0 == name).and_then(|h| h.1.parse::<u64>().ok())
+    }
+}
+
+/// Running totals for the requests a client has made.
+#[derive(Default)]
+pub struct Stats {
+    requests: u32,
+    retries: u32,
+    bytes: u64,
+    total: Duration,
+    lookup: Duration,
+    connect: Duration,
+    tls: Duration,
+    first_byte: Duration,
+}
+
+fn get_seconds(d: Duration) -> f64 {
+    d.as_secs() as f64 + d.subsec_nanos() as f64 / 1e9
+}
+
+fn get_now() -> Duration {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0))
+}
+
+// Double the delay on each attempt up to half a minute and pick a point in its
+// upper half at random, so clients that failed together do not retry together.
+fn get_backoff(attempt: u32) -> Duration {
+    let ceiling = cmp::min(500u64 << cmp::min(attempt, 6), 30000);
+    let jitter = get_now().subsec_nanos() as u64 % (ceiling / 2 + 1);
+    Duration::from_millis(ceiling / 2 + jitter)
+}
+
+fn is_idempotent(method: &str) -> bool {
+    match method {
+        "GET" | "HEAD" | "PUT" | "DELETE" => true,
+        _ => false,
+    }
+}
+
+/// HTTP client that keeps its handles, and so their connections, alive between requests.
+pub struct Client {
+    username: String,
+    password: Option<String>,
+    token: Option<String>,
+    retries: u32,
+    timeout: u64,
+    verbose: bool,
+    proxy: Option<String>,
+    proxy_username: Option<String>,
+    proxy_password: Option<String>,
+    no_proxy: Option<String>,
+    ca_bundle: Option<String>,
+    insecure: bool,
+    //#if USE_CURL_LIB
+    easy: RefCell<Easy2<Collector>>,
+    multi: Multi,
+    pool: RefCell<Vec<Easy2<Collector>>>,
+    //#endif
+    stats: RefCell<Stats>,
+}
+
+impl Client {
+    pub fn new(gh: &GitHub) -> Client {
+        //#if USE_CURL_LIB
+        let mut multi = Multi::new();
+        let _ = multi.pipelining(false, true);
+        //#endif
+        Client {
+            username: gh.get_username(),
+            password: gh.get_password(),
+            token: gh.get_token(),
+            retries: 3,
+            timeout: 0,
+            verbose: false,
+            proxy: None,
+            proxy_username: None,
+            proxy_password: None,
+            no_proxy: None,
+            ca_bundle: None,
+            insecure: false,
+            //#if USE_CURL_LIB
+            easy: RefCell::new(Easy2::new(Collector::new())),
+            multi: multi,
+            pool: RefCell::new(Vec::new()),
+            //#endif
+            stats: RefCell::new(Stats::default()),
+        }
+    }
+    pub fn set_retries(&mut self, retries: u32) {
+        self.retries = retries;
+    }
+    pub fn set_timeout(&mut self, timeout: u64) {
+        self.timeout = timeout;
+    }
+    pub fn set_verbose(&mut self, verbose: bool) {
+        self.verbose = verbose;
+    }
+    pub fn set_proxy(&mut self, proxy: Option<String>, username: Option<String>, password: Option<String>) {
+        self.proxy = proxy;
+        self.proxy_username = username;
+        self.proxy_password = password;
+    }
+    pub fn set_no_proxy(&mut self, no_proxy: Option<String>) {
+        self.no_proxy = no_proxy;
+    }
+    pub fn set_ca_bundle(&mut self, ca_bundle: Option<String>) {
+        self.ca_bundle = ca_bundle;
+    }
+    pub fn set_insecure(&mut self, insecure: bool) {
+        self.insecure = insecure;
+    }
+    //#if USE_CURL_LIB
+    // Resetting a handle clears its options but leaves its connection cache alone,
+    // so the next request to the same host skips the TCP and TLS handshakes.
+    fn prepare(&self, c: &mut Easy2<Collector>, method: &str, url: &str, body: &str) -> Result<(), Error> {
+        c.reset();
+        c.get_mut().data.clear();
+        c.get_mut().headers.clear();
+        c.url(url)?;
+        c.useragent("ghwcli")?;
+        let _ = c.http_version(HttpVersion::V2TLS);
+        if self.timeout > 0 {
+            c.timeout(Duration::from_secs(self.timeout))?;
+        }
+        if let Some(ref proxy) = self.proxy {
+            c.proxy(proxy)?;
+        }
+        if let Some(ref username) = self.proxy_username {
+            c.proxy_username(username)?;
+        }
+        if let Some(ref password) = self.proxy_password {
+            c.proxy_password(password)?;
+        }
+        if let Some(ref no_proxy) = self.no_proxy {
+            c.noproxy(no_proxy)?;
+        }
+        if let Some(ref ca_bundle) = self.ca_bundle {
+            c.cainfo(ca_bundle)?;
+        }
+        if self.insecure {
+            c.ssl_verify_peer(false)?;
+            c.ssl_verify_host(false)?;
+        }
+        // Renamed repositories and GHE raw hosts answer with redirects. libcurl keeps
+        // credentials to the first scheme, host and port only from 7.83 on, so older
+        // ones are not trusted to follow a redirect with a token or password along.
+        if Version::get().version_num() >= 0x075300 || (self.token.is_none() && self.password.is_none()) {
+            c.follow_location(true)?;
+            c.max_redirections(MAX_REDIRECTS)?;
+        }
+        let mut headers = List::new();
+        headers.append("Accept: application/vnd.github.v3+json")?;
+        if let Some(ref token) = self.token {
+            headers.append(&format!("Authorization: Bearer {}", token))?;
+        } else if let Some(ref password) = self.password {
+            c.username(&self.username)?;
+            c.password(password)?;
+        }
+        if method != "GET" {
+            c.custom_request(method)?;
+            c.post_fields_copy(body.as_bytes())?;
+            headers.append("Content-Type: application/json")?;
+        }
+        c.http_headers(headers)?;
+        Ok(())
+    }
+    fn finish(&self, c: &mut Easy2<Collector>) -> Result<Response, Error> {
+        let code = c.response_code()?;
+        let data = mem::replace(&mut c.get_mut().data, Vec::new());
+        let headers = mem::replace(&mut c.get_mut().headers, Vec::new());
+        let mut stats = self.stats.borrow_mut();
+        stats.requests += 1;
+        stats.bytes += data.len() as u64;
+        stats.total += c.total_time()?;
+        stats.lookup += c.namelookup_time()?;
+        stats.connect += c.connect_time()?;
+        stats.tls += c.appconnect_time()?;
+        stats.first_byte += c.starttransfer_time()?;
+        Ok(Response { code: code, data: data, headers: headers })
+    }
+    //#endif
+    // How long to wait before trying a request again, or None to let its result stand.
+    // Rate limits wait for the time GitHub gives, unless that is too long to sit out.
+    fn get_retry_delay(&self, method: &str, result: &Result<Response, Error>, attempt: u32)
+    -> Result<Option<Duration>, Error> {
+        if attempt >= self.retries || !is_idempotent(method) {
+            return Ok(None);
+        }
+        let response = match *result {
+            Ok(ref response) => response,
+            Err(Error::Network(_)) => return Ok(Some(get_backoff(attempt))),
+            Err(_) => return Ok(None),
+        };
+        let retry_after = response.get_header("retry-after");
+        let reset = response.get_header("x-ratelimit-reset");
+        let limited = response.code == 429 || (response.code == 403 &&
+        (retry_after.is_some() || response.get_header("x-ratelimit-remaining") == Some(0)));
+        if !limited {
+            return match response.code {
+                500 | 502 | 503 | 504 => {
+                    Ok(Some(retry_after.map(Duration::from_secs).unwrap_or(get_backoff(attempt))))
+                },
+                _ => Ok(None),
+            };
+        }
+        let wait = match (retry_after, reset) {
+            (Some(secs), _) => secs,
+            (None, Some(reset)) => reset.saturating_sub(get_now().as_secs()) + 1,
+            (None, None) => return Ok(Some(get_backoff(attempt))),
+        };
+        if wait > MAX_WAIT {
+            return Err(Error::Http(response.code, format!(
+                "API rate limit exceeded; it resets in {} minute(s), try again then", (wait + 59) / 60)));
+        }
+        Ok(Some(Duration::from_secs(wait)))
+    }
+    fn wait(&self, what: &str, count: u32, delay: Duration, attempt: u32) {
+        if self.verbose {
+            eprintln!("Retrying {} in {:.1}s (attempt {} of {}).",
+            what, get_seconds(delay), attempt + 1, self.retries);
+        }
+        self.stats.borrow_mut().retries += count;
+        thread::sleep(delay);
+    }
+    fn request_once(&self, method: &str, url: &str, body: &str) -> Result<Response, Error> {
+        //#if USE_CURL_LIB
+        let mut c = self.easy.borrow_mut();
+        self.prepare(&mut c, method, url, body)?;
+        c.perform()?;
+        self.finish(&mut c)
+        //#endif
+        //#if USE_CURL_EXT
+        println!("!TODO");
+        let _ = (method, url, body);
+        Ok(Response { code: 0, data: Vec::new(), headers: Vec::new() })
+        //#endif
+    }
+    pub fn request(&self, method: &str, url: &str, body: &str) -> Result<(u32, Vec<u8>), Error> {
+        let mut attempt = 0;
+        loop {
+            let result = self.request_once(method, url, body);
+            match self.get_retry_delay(method, &result, attempt)? {
+                Some(delay) => self.wait(url, 1, delay, attempt),
+                None => return result.map(|r| (r.code, r.data)),
+            }
+            attempt += 1;
+        }
+    }
+    // Run the transfers for the given indexes with up to jobs in flight on the multi
+    // handle, which shares connections between them, storing each result at its index.
+    fn transfer_all(&self, urls: &[String], indexes: &[usize], jobs: usize,
+    responses: &mut Vec<Option<Result<Response, Error>>>) -> Result<(), Error> {
+        //#if USE_CURL_LIB
+        let multi = &self.multi;
+        let mut pool = self.pool.borrow_mut();
+        let mut active = Vec::new();
+        let mut next = 0;
+        while next < indexes.len() || !active.is_empty() {
+            while next < indexes.len() && active.len() < jobs {
+                let index = indexes[next];
+                let mut c = pool.pop().unwrap_or_else(|| Easy2::new(Collector::new()));
+                match self.prepare(&mut c, "GET", &urls[index], "") {
+                    Ok(()) => active.push((index, multi.add2(c)?)),
+                    Err(err) => responses[index] = Some(Err(err)),
+                }
+                next += 1;
+            }
+            multi.perform()?;
+            let mut done = Vec::new();
+            multi.messages(|msg| {
+                for (i, &(_, ref handle)) in active.iter().enumerate() {
+                    if let Some(result) = msg.result_for2(handle) {
+                        done.push((i, result));
+                    }
+                }
+            });
+            done.sort_by(|a, b| b.0.cmp(&a.0));
+            for (i, result) in done {
+                let (index, handle) = active.remove(i);
+                let mut c = multi.remove2(handle)?;
+                responses[index] = Some(match result {
+                    Ok(()) => self.finish(&mut c),
+                    Err(err) => Err(Error::from(err)),
+                });
+                pool.push(c);
+            }
+            if !active.is_empty() {
+                multi.wait(&mut [], Duration::from_secs(1))?;
+            }
+        }
+        //#endif
+        //#if USE_CURL_EXT
+        println!("!TODO");
+        let _ = (urls, indexes, jobs, responses);
+        //#endif
+        Ok(())
+    }
+    // Fetch the URLs concurrently, retrying the ones that failed in another round,
+    // and give back the responses in the order asked for.
+    pub fn request_all(&self, urls: &[String], jobs: usize)
+    -> Result<Vec<Result<(u32, Vec<u8>), Error>>, Error> {
+        let mut responses: Vec<Option<Result<Response, Error>>> = urls.iter().map(|_| None).collect();
+        let mut pending: Vec<usize> = (0..urls.len()).collect();
+        let mut attempt = 0;
+        while !pending.is_empty() {
+            self.transfer_all(urls, &pending, jobs, &mut responses)?;
+            let mut retry: Vec<usize> = Vec::new();
+            let mut delay = Duration::from_secs(0);
+            for index in pending {
+                if let Some(ref result) = responses[index] {
+                    if let Some(d) = self.get_retry_delay("GET", result, attempt)? {
+                        retry.push(index);
+                        delay = cmp::max(delay, d);
+                    }
+                }
+            }
+            if !retry.is_empty() {
+                self.wait(&format!("{} request(s)", retry.len()), retry.len() as u32, delay, attempt);
+            }
+            pending = retry;
+            attempt += 1;
+        }
+        Ok(responses.into_iter().map(|r| match r {
+            Some(result) => result.map(|r| (r.code, r.data)),
+            None => Err(Error::Network("Transfer did not finish".to_owned())),
+        }).collect())
+    }
+    pub fn display_stats(&self) {
+        let stats = self.stats.borrow();
+        if stats.requests == 0 {
+            return;
+        }
+        eprintln!("{} request(s), {} retried, {} byte(s) in {:.3}s \
+        (lookup {:.3}s, connect {:.3}s, TLS {:.3}s, first byte {:.3}s).",
+        stats.requests, stats.retries, stats.bytes, get_seconds(stats.total),
+        get_seconds(stats.lookup), get_seconds(stats.connect), get_seconds(stats.tls),
+        get_seconds(stats.first_byte));
+    }
+}
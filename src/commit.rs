@@ -0,0 +1,30 @@
+#[derive(Debug, RustcDecodable, RustcEncodable)]
+pub struct PendingCommit {
+    sha: String,
+    tree: String,
+    parent: String,
+    message: String,
+}
+
+impl PendingCommit {
+    pub fn new(sha: &str, tree: &str, parent: &str, message: &str) -> PendingCommit {
+        PendingCommit {
+            sha: sha.to_owned(),
+            tree: tree.to_owned(),
+            parent: parent.to_owned(),
+            message: message.to_owned(),
+        }
+    }
+    pub fn get_sha(&self) -> String {
+        self.sha.clone()
+    }
+    pub fn get_tree(&self) -> String {
+        self.tree.clone()
+    }
+    pub fn get_parent(&self) -> String {
+        self.parent.clone()
+    }
+    pub fn get_message(&self) -> String {
+        self.message.clone()
+    }
+}
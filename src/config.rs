@@ -0,0 +1,68 @@
+use rustc_serialize::json::Json;
+use std::collections::BTreeMap;
+
+pub const ACCOUNT_KEYS: &'static [&'static str] = &[
+    "username", "password", "token", "token_file", "credential_helper", "web_url",
+    "api_url", "raw_url",
+];
+pub const PROJECT_KEYS: &'static [&'static str] = &["owner", "name", "branch", "tag", "rev"];
+pub const NETWORK_KEYS: &'static [&'static str] = &[
+    "proxy", "proxy_username", "proxy_password", "no_proxy", "ca_bundle", "insecure",
+];
+pub const SECRET_KEYS: &'static [&'static str] = &["password", "token", "proxy_password"];
+
+pub struct Config {
+    values: BTreeMap<String, (Json, String)>,
+}
+
+impl Config {
+    pub fn new() -> Config {
+        Config {
+            values: BTreeMap::new(),
+        }
+    }
+    pub fn is_key(key: &str) -> bool {
+        key == "profile" || ACCOUNT_KEYS.contains(&key) || PROJECT_KEYS.contains(&key) ||
+        NETWORK_KEYS.contains(&key)
+    }
+    pub fn merge(&mut self, origin: &str, layer: &Json) {
+        if let Some(fields) = layer.as_object() {
+            for (key, value) in fields {
+                if !value.is_null() && key != "profiles" {
+                    self.set(key, value.clone(), origin);
+                }
+            }
+        }
+    }
+    pub fn set(&mut self, key: &str, value: Json, origin: &str) {
+        self.values.insert(key.to_owned(), (value, origin.to_owned()));
+    }
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        self.values.get(key).map(|v| &v.0)
+    }
+    pub fn get_string(&self, key: &str) -> Option<String> {
+        match self.get(key) {
+            Some(&Json::String(ref s)) => Some(s.clone()),
+            Some(v) => Some(v.to_string()),
+            None => None,
+        }
+    }
+    pub fn get_origin(&self, key: &str) -> Option<String> {
+        self.values.get(key).map(|v| v.1.clone())
+    }
+    pub fn has(&self, key: &str) -> bool {
+        self.values.contains_key(key)
+    }
+    pub fn list(&self) -> Vec<(String, Json, String)> {
+        self.values.iter().map(|(k, v)| (k.clone(), v.0.clone(), v.1.clone())).collect()
+    }
+    pub fn to_json(&self, keys: &[&str]) -> Json {
+        let mut o = BTreeMap::new();
+        for key in keys {
+            if let Some(value) = self.get(key) {
+                o.insert(key.to_string(), value.clone());
+            }
+        }
+        Json::Object(o)
+    }
+}
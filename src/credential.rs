@@ -0,0 +1,100 @@
+use std::io::{Read, Write};
+use std::process::{Command, Stdio};
+
+pub struct CredentialHelper {
+    helper: String,
+}
+
+impl CredentialHelper {
+    pub fn new(helper: &str) -> CredentialHelper {
+        CredentialHelper {
+            helper: helper.to_owned(),
+        }
+    }
+    fn is_keyring(&self) -> bool {
+        self.helper == "keyring"
+    }
+    // Resolve the helper the same way git does: "!cmd" runs through the shell,
+    // an absolute path runs as is and a bare name runs git credential-<name>.
+    fn get_command(&self, action: &str) -> Command {
+        if self.helper.starts_with("!") {
+            let mut cmd = Command::new("sh");
+            cmd.arg("-c").arg(format!("{} {}", &self.helper[1..], action));
+            cmd
+        } else if self.helper.starts_with("/") {
+            let mut cmd = Command::new(&self.helper);
+            cmd.arg(action);
+            cmd
+        } else {
+            let mut cmd = Command::new("git");
+            cmd.arg(format!("credential-{}", self.helper)).arg(action);
+            cmd
+        }
+    }
+    fn get_keyring_command(&self, action: &str, host: &str, username: &str) -> Command {
+        let mut cmd = Command::new("secret-tool");
+        cmd.arg(action);
+        if action == "store" {
+            cmd.arg("--label").arg(format!("ghwcli token for {}@{}", username, host));
+        }
+        cmd.args(&["service", "ghwcli", "host", host, "username", username]);
+        cmd
+    }
+    fn run(&self, mut cmd: Command, input: &str) -> Option<String> {
+        let mut child = match cmd.stdin(Stdio::piped()).stdout(Stdio::piped()).spawn() {
+            Ok(child) => child,
+            Err(_) => return None,
+        };
+        if let Some(mut stdin) = child.stdin.take() {
+            let _ = stdin.write_all(input.as_bytes());
+        }
+        let mut output = String::new();
+        if let Some(mut stdout) = child.stdout.take() {
+            let _ = stdout.read_to_string(&mut output);
+        }
+        match child.wait() {
+            Ok(status) if status.success() => Some(output),
+            _ => None,
+        }
+    }
+    fn get_description(&self, host: &str, username: &str, secret: &str) -> String {
+        let mut desc = format!("protocol=https\nhost={}\nusername={}\n", host, username);
+        if !secret.is_empty() {
+            desc.push_str(&format!("password={}\n", secret));
+        }
+        desc.push_str("\n");
+        desc
+    }
+    pub fn get(&self, host: &str, username: &str) -> Option<String> {
+        if self.is_keyring() {
+            let cmd = self.get_keyring_command("lookup", host, username);
+            return self.run(cmd, "").map(|s| s.trim().to_owned()).and_then(|s| {
+                if s.is_empty() { None } else { Some(s) }
+            });
+        }
+        let output = match self.run(self.get_command("get"), &self.get_description(host, username, "")) {
+            Some(output) => output,
+            None => return None,
+        };
+        for line in output.lines() {
+            if line.starts_with("password=") {
+                return Some(line["password=".len()..].to_owned());
+            }
+        }
+        None
+    }
+    pub fn store(&self, host: &str, username: &str, secret: &str) -> bool {
+        if self.is_keyring() {
+            let cmd = self.get_keyring_command("store", host, username);
+            return self.run(cmd, secret).is_some();
+        }
+        self.run(self.get_command("store"), &self.get_description(host, username, secret)).is_some()
+    }
+    pub fn erase(&self, host: &str, username: &str) -> bool {
+        if self.is_keyring() {
+            let cmd = self.get_keyring_command("clear", host, username);
+            return self.run(cmd, "").is_some();
+        }
+        self.run(self.get_command("erase"), &self.get_description(host, username, "")).is_some()
+    }
+}
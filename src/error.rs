@@ -0,0 +1,83 @@
+use rustc_serialize::json;
+use std::fmt;
+use std::io;
+
+/// Failures that stop a command, each mapped to its own exit code.
+#[derive(Debug)]
+pub enum Error {
+    /// Could not reach the server (exit code 2).
+    Network(String),
+    /// The server answered with an unexpected HTTP status (exit code 3).
+    Http(u32, String),
+    /// The credentials were missing or rejected (exit code 4).
+    Auth(String),
+    /// A configuration file or value could not be used (exit code 5).
+    Config(String),
+    /// Reading or writing a local file failed (exit code 6).
+    Io(String),
+    /// The working copy is missing or cannot take the operation (exit code 7).
+    WorkingCopy(String),
+}
+
+impl Error {
+    pub fn from_io(path: &str, err: io::Error) -> Error {
+        Error::Io(format!("{}: {}", path, err))
+    }
+    pub fn with_message(&self, msg: &str) -> Error {
+        match *self {
+            Error::Network(_) => Error::Network(msg.to_owned()),
+            Error::Http(code, _) => Error::Http(code, msg.to_owned()),
+            Error::Auth(_) => Error::Auth(msg.to_owned()),
+            Error::Config(_) => Error::Config(msg.to_owned()),
+            Error::Io(_) => Error::Io(msg.to_owned()),
+            Error::WorkingCopy(_) => Error::WorkingCopy(msg.to_owned()),
+        }
+    }
+    pub fn get_code(&self) -> i32 {
+        match *self {
+            Error::Network(_) => 2,
+            Error::Http(_, _) => 3,
+            Error::Auth(_) => 4,
+            Error::Config(_) => 5,
+            Error::Io(_) => 6,
+            Error::WorkingCopy(_) => 7,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Network(ref msg) => write!(f, "Network error: {}", msg),
+            Error::Http(code, ref msg) => write!(f, "GitHub returned HTTP {}: {}", code, msg),
+            Error::Auth(ref msg) => write!(f, "Authentication failed: {}", msg),
+            Error::Config(ref msg) => write!(f, "Configuration error: {}", msg),
+            Error::Io(ref msg) => write!(f, "I/O error: {}", msg),
+            Error::WorkingCopy(ref msg) => write!(f, "{}", msg),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err.to_string())
+    }
+}
+
+impl From<json::DecoderError> for Error {
+    fn from(err: json::DecoderError) -> Error {
+        Error::Config(err.to_string())
+    }
+}
+
+impl From<json::EncoderError> for Error {
+    fn from(err: json::EncoderError) -> Error {
+        Error::Config(err.to_string())
+    }
+}
+
+impl From<json::ParserError> for Error {
+    fn from(err: json::ParserError) -> Error {
+        Error::Config(err.to_string())
+    }
+}
@@ -0,0 +1,159 @@
+use regex::{escape, Regex};
+
+/// Which files a sparse clone keeps: those under `path` that match an
+/// `include` glob (any, when there are none) and no `exclude` glob.
+#[derive(Debug, Clone, RustcDecodable, RustcEncodable)]
+pub struct Filter {
+    path: String,
+    include: Vec<String>,
+    exclude: Vec<String>,
+}
+
+pub struct Matcher {
+    path: String,
+    include: Vec<Regex>,
+    exclude: Vec<Regex>,
+}
+
+impl Filter {
+    pub fn new(path: &str, include: Vec<String>, exclude: Vec<String>) -> Filter {
+        Filter {
+            path: path.trim_left_matches("./").trim_matches('/').to_owned(),
+            include: include,
+            exclude: exclude,
+        }
+    }
+    pub fn is_empty(&self) -> bool {
+        self.path.is_empty() && self.include.is_empty() && self.exclude.is_empty()
+    }
+    pub fn matcher(&self) -> Result<Matcher, String> {
+        Ok(Matcher {
+            path: self.path.clone(),
+            include: self.include.iter().map(|g| glob_to_regex(g)).collect::<Result<_, _>>()?,
+            exclude: self.exclude.iter().map(|g| glob_to_regex(g)).collect::<Result<_, _>>()?,
+        })
+    }
+    pub fn describe(&self) -> String {
+        let mut parts = Vec::new();
+        if !self.path.is_empty() {
+            parts.push(format!("path {}", self.path));
+        }
+        if !self.include.is_empty() {
+            parts.push(format!("include {}", self.include.join(", ")));
+        }
+        if !self.exclude.is_empty() {
+            parts.push(format!("exclude {}", self.exclude.join(", ")));
+        }
+        parts.join("; ")
+    }
+}
+
+impl Matcher {
+    pub fn is_match(&self, file: &str) -> bool {
+        if !self.path.is_empty() && file != self.path && !file.starts_with(&format!("{}/", self.path)) {
+            return false;
+        }
+        (self.include.is_empty() || self.include.iter().any(|r| r.is_match(file)))
+        && !self.exclude.iter().any(|r| r.is_match(file))
+    }
+}
+
+// Globs follow .gitignore: * and ? stay within a directory, ** crosses them,
+// a glob without a slash matches a name at any depth and a glob that matches
+// a directory takes in everything below it.
+fn glob_to_regex(pattern: &str) -> Result<Regex, String> {
+    let glob = pattern.trim_right_matches('/');
+    let anchored = glob.contains('/');
+    let chars: Vec<char> = glob.trim_left_matches('/').chars().collect();
+    let mut re = String::from(if anchored { "^" } else { "^(?:.*/)?" });
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                if chars.get(i + 2) == Some(&'/') {
+                    re.push_str("(?:.*/)?");
+                    i += 1;
+                } else {
+                    re.push_str(".*");
+                }
+                i += 1;
+            },
+            '*' => re.push_str("[^/]*"),
+            '?' => re.push_str("[^/]"),
+            '[' => match chars[i + 1..].iter().position(|&c| c == ']') {
+                Some(n) if n > 0 => {
+                    let class: String = chars[i + 1..i + 1 + n].iter().collect();
+                    let class = if class.starts_with('!') { format!("^{}", &class[1..]) } else { class };
+                    let class = class.replace("\\", "\\\\").replace("[", "\\[")
+                    .replace("&", "\\&").replace("~", "\\~");
+                    re.push_str(&format!("[{}]", class));
+                    i += n + 1;
+                },
+                _ => re.push_str(&escape("[")),
+            },
+            c => re.push_str(&escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    re.push_str("(?:/.*)?$");
+    Regex::new(&re).map_err(|_| format!("Invalid glob '{}'", pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{glob_to_regex, Filter};
+
+    fn matches(glob: &str, path: &str) -> bool {
+        glob_to_regex(glob).unwrap().is_match(path)
+    }
+
+    #[test]
+    fn names_match_at_any_depth() {
+        assert!(matches("*.md", "README.md"));
+        assert!(matches("*.md", "docs/guide.md"));
+        assert!(!matches("*.md", "README.mdx"));
+        assert!(matches("vendor", "src/vendor/lib.rs"));
+    }
+
+    #[test]
+    fn slashes_anchor_to_the_root() {
+        assert!(matches("docs/*.md", "docs/guide.md"));
+        assert!(!matches("docs/*.md", "docs/api/index.md"));
+        assert!(!matches("docs/*.md", "site/docs/guide.md"));
+        assert!(matches("/README.md", "README.md"));
+    }
+
+    #[test]
+    fn double_stars_cross_directories() {
+        assert!(matches("vendor/**", "vendor/x/lib.md"));
+        assert!(matches("src/**/*.txt", "src/a.txt"));
+        assert!(matches("src/**/*.txt", "src/deep/a/b.txt"));
+        assert!(!matches("src/**/*.txt", "docs/a.txt"));
+    }
+
+    #[test]
+    fn single_characters_and_classes() {
+        assert!(matches("?.rs", "a.rs"));
+        assert!(!matches("?.rs", "ab.rs"));
+        assert!(matches("[!R]*.sh", "run.sh"));
+        assert!(!matches("[!R]*.sh", "Run.sh"));
+        assert!(matches("[a-c].txt", "b.txt"));
+        assert!(matches("x[.md", "x[.md"));
+    }
+
+    #[test]
+    fn bad_globs_are_errors() {
+        assert!(glob_to_regex("[z-a]").is_err());
+        assert!(Filter::new("", vec!["[!]".to_owned()], Vec::new()).matcher().is_err());
+    }
+
+    #[test]
+    fn filters_combine_path_include_and_exclude() {
+        let filter = Filter::new("./docs/", vec!["*.md".to_owned()], vec!["drafts".to_owned()]);
+        let m = filter.matcher().unwrap();
+        assert!(m.is_match("docs/guide.md"));
+        assert!(!m.is_match("docs/logo.png"));
+        assert!(!m.is_match("README.md"));
+        assert!(!m.is_match("docs/drafts/x.md"));
+    }
+}
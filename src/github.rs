@@ -1,23 +1,97 @@
 #[derive(Debug, RustcDecodable, RustcEncodable)]
 pub struct GitHub {
     username: String,
-    password: String,
+    password: Option<String>, // Deprecated: GitHub rejects passwords for API access.
+    token: Option<String>,
+    token_file: Option<String>,
+    credential_helper: Option<String>,
+    web_url: Option<String>,
+    api_url: Option<String>,
+    raw_url: Option<String>,
 }
 
 impl GitHub {
-    pub fn new(username: &str, password: &str) -> GitHub {
+    pub fn new(username: &str) -> GitHub {
         GitHub {
             username: username.to_owned(),
-            password: password.to_owned(),
+            password: None,
+            token: None,
+            token_file: None,
+            credential_helper: None,
+            web_url: None,
+            api_url: None,
+            raw_url: None,
         }
     }
+    pub fn get_username(&self) -> String {
+        self.username.clone()
+    }
+    pub fn get_password(&self) -> Option<String> {
+        self.password.clone()
+    }
+    pub fn get_token(&self) -> Option<String> {
+        self.token.clone()
+    }
+    pub fn set_token(&mut self, token: &str) {
+        self.token = Some(token.to_owned());
+    }
+    pub fn get_token_file(&self) -> Option<String> {
+        self.token_file.clone()
+    }
+    pub fn get_credential_helper(&self) -> Option<String> {
+        self.credential_helper.clone()
+    }
+    pub fn set_credential_helper(&mut self, helper: &str) {
+        self.credential_helper = Some(helper.to_owned());
+    }
+    // Drop the password and token settings, saying whether there were any.
+    pub fn clear_credentials(&mut self) -> bool {
+        let had = self.password.is_some() || self.token.is_some() || self.token_file.is_some();
+        self.password = None;
+        self.token = None;
+        self.token_file = None;
+        had
+    }
+    // GitHub Enterprise Server answers on one host, with the API under /api/v3
+    // and raw file contents under /raw, so both follow a configured web URL.
+    fn is_enterprise(&self) -> bool {
+        self.get_host() != "github.com"
+    }
+    pub fn get_web_url(&self) -> String {
+        match self.web_url {
+            Some(ref url) => url.trim_right_matches('/').to_owned(),
+            None => "https://github.com".to_owned(),
+        }
+    }
+    pub fn set_web_url(&mut self, url: &str) {
+        self.web_url = Some(url.to_owned());
+    }
+    pub fn get_host(&self) -> String {
+        let url = self.get_web_url();
+        let host = url.splitn(2, "://").last().unwrap_or("");
+        host.split('/').next().unwrap_or("").to_owned()
+    }
     pub fn get_base_url(&self) -> String {
-        "https://raw.githubusercontent.com".to_owned()
+        match self.raw_url {
+            Some(ref url) => url.trim_right_matches('/').to_owned(),
+            None if self.is_enterprise() => format!("{}/raw", self.get_web_url()),
+            None => "https://raw.githubusercontent.com".to_owned(),
+        }
     }
     pub fn get_url_frag(&self) -> String {
         format!("{}/{}/", self.get_base_url(), self.username)
     }
     pub fn get_index_frag(&self) -> String {
-        format!("https://github.com/{}/", self.username)
+        format!("{}/", self.get_web_url())
+    }
+    pub fn get_api_url(&self) -> String {
+        match self.api_url {
+            Some(ref url) => url.trim_right_matches('/').to_owned(),
+            None if self.is_enterprise() => format!("{}/api/v3", self.get_web_url()),
+            None => "https://api.github.com".to_owned(),
+        }
+    }
+    pub fn get_api_frag(&self) -> String {
+        format!("{}/repos/", self.get_api_url())
     }
 }
@@ -8,209 +8,1532 @@
 
 mod github;
 mod project;
+mod commit;
+mod metadata;
+mod credential;
+mod config;
+mod error;
+mod http;
+mod unified;
+mod remote;
+mod filter;
+mod merge;
+#[cfg(test)]
+mod tests;
 extern crate curl;
-extern crate text_diff;
 extern crate rustc_serialize;
 extern crate regex;
-extern crate select;
 extern crate clioptions;
+extern crate sha1;
+extern crate rpassword;
+extern crate libc;
 use github::GitHub;
 use project::Project;
-use curl::easy::Easy as CurlRequest;
-use text_diff::{diff, print_diff, Difference};
+use commit::PendingCommit;
+use metadata::{Entry, Metadata};
+use credential::CredentialHelper;
+use config::{Config, ACCOUNT_KEYS, PROJECT_KEYS, SECRET_KEYS};
+use error::Error;
+use http::Client;
+use unified::unified_diff;
+use remote::Remote;
+use filter::Filter;
+use merge::merge3;
 use rustc_serialize::json;
 use rustc_serialize::json::Json;
-use regex::Regex;
-use select::document::Document;
-use select::predicate::Name;
+use rustc_serialize::base64::{ToBase64, STANDARD};
 use clioptions::CliOptions;
-use std::io::{stdin, stdout, Read, Write};
+use std::io::{stdin, Read, Write};
 use std::fs;
 use std::fs::File;
 use std::path::Path;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str;
 use std::process::exit;
+use std::env;
 
-fn split_path_from_file(pathstr: &str) -> String {
-    let split = pathstr.split("/");
-    let mut path: Vec<String> = Vec::new();
-    for s in split {
-        if s.to_owned().len() > 0 {
-            path.push(s.to_owned())
+fn get_fetch_error(code: u32, content: &[u8]) -> Error {
+    let rate_limited = String::from_utf8_lossy(content).to_lowercase().contains("rate limit");
+    match code {
+        429 => Error::Http(code, "rate limit exceeded".to_owned()),
+        403 if rate_limited => Error::Http(code, "rate limit exceeded".to_owned()),
+        401 => Error::Auth("not authorized; check your token".to_owned()),
+        403 => Error::Auth("access forbidden; check your token's permissions".to_owned()),
+        404 => Error::Http(code, "file not found".to_owned()),
+        _ => Error::Http(code, "unexpected response".to_owned()),
+    }
+}
+
+// Escape everything but unreserved characters in a path segment, so names with
+// spaces, '#', '?' or '%' in them still point at the right file.
+fn encode_segment(segment: &str) -> String {
+    let mut encoded = String::new();
+    for &b in segment.as_bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
         }
     }
-    path.pop();
-    path.join("/")
+    encoded
+}
+
+fn get_file_url(gh: &GitHub, project: &Project, rev: &str, file: &str) -> String {
+    let path: Vec<String> = file.split('/').map(encode_segment).collect();
+    format!("{}/{}/{}/{}", gh.get_base_url(), project.get_full_name(), encode_segment(rev), path.join("/"))
+}
+
+fn write_working_file(wd: &str, file: &str, content: &[u8]) -> Result<(), Error> {
+    let out = format!("{}/{}", wd, file);
+    if let Some(p) = Path::new(&out).parent() {
+        fs::create_dir_all(p).map_err(|e| Error::from_io(&out, e))?;
+    }
+    let mut w = File::create(&out).map_err(|e| Error::from_io(&out, e))?;
+    w.write_all(content).map_err(|e| Error::from_io(&out, e))
+}
+
+fn get_path_depth(path: &str) -> u32 {
+    path.split("/").count() as u32
 }
 
-fn split_url_from_blob(burl: &str) -> String {
-    let split = burl.split("/blob/");
-    let mut url: Vec<String> = Vec::new();
-    for s in split {
-        if s.to_owned().len() > 0 {
-            url.push(s.to_owned())
+fn retrieve_repo(gh: &GitHub, client: &Client, project: &Project, wd: &str, profile: Option<String>,
+filter: &Filter, depth: u32, jobs: usize, keep_going: bool, verbose: bool) -> Result<(), Error> {
+    let md = get_meta_dir(wd);
+    let previous = check_clone_destination(&project, wd)?;
+    let head = get_remote_commit(&gh, &client, &project)?;
+    let tree = get_commit_tree(&gh, &client, &project, &head)?;
+    if verbose {
+        println!("Cloning {}{} at {} ({}) into {}",
+        gh.get_index_frag(), project.get_full_name(), project.get_ref(), &head[..7], wd);
+    }
+    let mut meta = Metadata::new(&project.get_owner(), &project.get_name(), &project.get_branch(), &head);
+    meta.set_ref(project.get_tag(), project.get_rev());
+    meta.set_hosts(&gh.get_web_url(), &gh.get_api_url(), &gh.get_base_url());
+    meta.set_profile(profile);
+    meta.set_depth(depth);
+    meta.set_filter(filter);
+    let mut blobs: Vec<(String, String)> = Vec::new();
+    let matcher = filter.matcher().map_err(Error::Config)?;
+    for (file, (mode, sha)) in get_remote_tree(&gh, &client, &project, &tree)? {
+        if depth > 0 && get_path_depth(&file) > depth {
+            continue;
+        }
+        if is_reserved_path(&file) {
+            if mode != "040000" {
+                eprintln!("Warning: Left out {}; ghwcli keeps that path for its own files.", file);
+            }
+            continue;
+        }
+        // A sparse clone only makes the directories its files are written into.
+        if mode == "040000" && filter.is_empty() {
+            let dir = format!("{}/{}", wd, file);
+            fs::create_dir_all(&dir).map_err(|e| Error::from_io(&dir, e))?;
+        } else if mode != "040000" && matcher.is_match(&file) {
+            blobs.push((file, sha));
+        }
+    }
+    // Files already here with the right blob SHA need not be downloaded again.
+    let mut skipped = 0;
+    let mut fetches: Vec<(String, String)> = Vec::new();
+    for (path, sha) in blobs {
+        match get_local_blob(wd, &path, &sha) {
+            Some(content) => {
+                write_working_file(wd, &path, &content)?;
+                store_object(&md, &sha, &content)?;
+                meta.add_entry(Entry::new(&path, &get_content_hash(&content), &sha));
+                skipped += 1;
+            },
+            None => fetches.push((path, sha)),
+        }
+    }
+    let urls: Vec<String> = fetches.iter()
+    .map(|&(ref path, _)| get_file_url(&gh, &project, &head, path)).collect();
+    let responses = client.request_all(&urls, jobs)?;
+    let fetched = urls.len();
+    let mut failures: Vec<(String, Error)> = Vec::new();
+    for (((path, sha), url), response) in fetches.into_iter().zip(urls).zip(responses) {
+        let content = match response {
+            Ok((code, content)) => {
+                if verbose {
+                    println!("Retrieved file: {} [{}]", url, code);
+                }
+                if code != 200 {
+                    failures.push((path, get_fetch_error(code, &content)));
+                    continue;
+                }
+                content
+            },
+            Err(err) => {
+                failures.push((path, err));
+                continue;
+            }
+        };
+        write_working_file(wd, &path, &content)?;
+        if git_blob_sha(&content) != sha {
+            eprintln!("Warning: {} does not match its blob {}.", path, &sha[..7]);
+        }
+        store_object(&md, &sha, &content)?;
+        meta.add_entry(Entry::new(&path, &get_content_hash(&content), &sha));
+    }
+    if let Some(previous) = previous {
+        for entry in previous.get_entries() {
+            let path = format!("{}/{}", wd, entry.get_path());
+            if meta.find(&entry.get_path()).is_none() && !is_reserved_path(&entry.get_path())
+            && Path::new(&path).exists() {
+                fs::remove_file(&path).map_err(|e| Error::from_io(&path, e))?;
+            }
         }
     }
-    url.join("/")
+    let _ = fs::remove_file(format!("{}/commits.json", md));
+    write_metadata(&md, &meta)?;
+    if verbose {
+        println!("Fetched {} file(s), skipped {} unchanged.", fetched - failures.len(), skipped);
+    }
+    if failures.is_empty() {
+        return Ok(());
+    }
+    eprintln!("{}: could not fetch {} file(s):", if keep_going { "Warning" } else { "Error" }, failures.len());
+    for &(ref path, ref err) in &failures {
+        eprintln!("  {}: {}", path, err);
+    }
+    if keep_going {
+        return Ok(());
+    }
+    let message = format!("Clone of {} is incomplete (use --keep-going to accept a partial clone)",
+    project.get_full_name());
+    Err(failures[0].1.with_message(&message))
+}
+
+// A clone may only go into an empty directory, or over an earlier clone of the
+// same project that has nothing uncommitted or unpushed, whose metadata is returned.
+fn check_clone_destination(project: &Project, wd: &str) -> Result<Option<Metadata>, Error> {
+    if !fs::read_dir(wd).map(|mut d| d.next().is_some()).unwrap_or(false) {
+        return Ok(None);
+    }
+    let md = get_meta_dir(wd);
+    if !Path::new(&format!("{}/index.json", md)).exists() {
+        return Err(Error::WorkingCopy(format!("{} already exists and is not an empty directory", wd)));
+    }
+    let meta = load_metadata(&md)?;
+    if meta.get_owner() != project.get_owner() || meta.get_repo() != project.get_name() {
+        return Err(Error::WorkingCopy(format!("{} is a working copy of {}/{}, not {}",
+        wd, meta.get_owner(), meta.get_repo(), project.get_full_name())));
+    }
+    let changed = get_status(wd, &meta)?.iter().any(|&(x, y, _)| x != '?' || y != '?');
+    if changed || !load_pending_commits(&md)?.is_empty() {
+        return Err(Error::WorkingCopy(format!(
+            "{} has local changes; commit and push them or use pull instead of cloning again", wd)));
+    }
+    Ok(Some(meta))
 }
 
-fn split_dir_from_tree(url: &str) -> String {
-    let split = url.split("/tree/");
-    let mut dir: Vec<String> = Vec::new();
-    for s in split {
-        if s.to_owned().len() > 0 {
-            dir.push(s.to_owned());
+// The content of a file whose working copy already matches the blob SHA.
+fn get_local_blob(wd: &str, file: &str, sha: &str) -> Option<Vec<u8>> {
+    match read_file_bytes(&format!("{}/{}", wd, file)) {
+        Ok(ref content) if git_blob_sha(content) == sha => Some(content.clone()),
+        _ => None,
+    }
+}
+
+// Project configuration files, read from the working copy root when inside one
+// and never cloned, listed or committed as part of it.
+const CONFIG_FILES: [&'static str; 2] = [".github.json", ".project.json"];
+
+// Paths in a repository that would land on ghwcli's own files: the configuration
+// files and the metadata directory at the root of the working copy.
+fn is_reserved_path(file: &str) -> bool {
+    CONFIG_FILES.contains(&file) || file == ".ghwcli" || file.starts_with(".ghwcli/")
+}
+
+fn get_config_path(root: &Option<String>, name: &str) -> String {
+    match *root {
+        Some(ref root) => format!("{}/{}", root, name),
+        None => name.to_owned(),
+    }
+}
+
+fn get_meta_dir(wd: &str) -> String {
+    format!("{}/.ghwcli", wd)
+}
+
+// Walk up from the current directory to the first one holding clone metadata,
+// the way git looks for its .git directory.
+fn find_working_copy() -> Result<String, Error> {
+    let cwd = env::current_dir().map_err(|e| Error::from_io(".", e))?;
+    let mut dir = Some(cwd.as_path());
+    while let Some(d) = dir {
+        if d.join(".ghwcli").join("index.json").exists() {
+            return Ok(d.to_string_lossy().into_owned());
         }
+        dir = d.parent();
     }
-    dir.remove(0);
-    dir.join("/")
+    Err(Error::WorkingCopy(format!("{} is not inside a working copy; clone one first", cwd.display())))
 }
 
-fn retrieve_file(gh: &GitHub, project: &Project, file: &str, verbose: bool, index: u32) {
-    let mut c = CurlRequest::new();
-    if index == 1 {
-        c.url(&format!("{}{}", gh.get_index_frag(), project.get_index_frag())).unwrap();
+fn get_copy_configuration(wd: &str) -> Result<Json, Error> {
+    let meta = load_metadata(&get_meta_dir(wd))?;
+    let mut o = BTreeMap::new();
+    o.insert("owner".to_owned(), Json::String(meta.get_owner()));
+    o.insert("name".to_owned(), Json::String(meta.get_repo()));
+    o.insert("branch".to_owned(), Json::String(meta.get_branch()));
+    o.insert("tag".to_owned(), Json::String(meta.get_tag().unwrap_or(String::new())));
+    o.insert("rev".to_owned(), Json::String(meta.get_rev().unwrap_or(String::new())));
+    for (key, value) in meta.get_hosts() {
+        if let Some(url) = value {
+            o.insert(key.to_owned(), Json::String(url));
+        }
+    }
+    if let Some(profile) = meta.get_profile() {
+        o.insert("profile".to_owned(), Json::String(profile));
+    }
+    Ok(Json::Object(o))
+}
+
+fn git_blob_sha(content: &[u8]) -> String {
+    let mut m = sha1::Sha1::new();
+    m.update(format!("blob {}\0", content.len()).as_bytes());
+    m.update(content);
+    m.digest().to_string()
+}
+
+fn get_content_hash(content: &[u8]) -> String {
+    let mut m = sha1::Sha1::new();
+    m.update(content);
+    m.digest().to_string()
+}
+
+fn read_file_bytes(path: &str) -> Result<Vec<u8>, Error> {
+    let mut content: Vec<u8> = Vec::new();
+    let mut f = File::open(path).map_err(|e| Error::from_io(path, e))?;
+    f.read_to_end(&mut content).map_err(|e| Error::from_io(path, e))?;
+    Ok(content)
+}
+
+fn store_object(md: &str, sha: &str, content: &[u8]) -> Result<(), Error> {
+    let objects = format!("{}/objects", md);
+    let out = format!("{}/{}", objects, sha);
+    if !Path::new(&out).exists() {
+        fs::create_dir_all(&objects).map_err(|e| Error::from_io(&objects, e))?;
+        let mut w = File::create(&out).map_err(|e| Error::from_io(&out, e))?;
+        w.write_all(content).map_err(|e| Error::from_io(&out, e))?;
+    }
+    Ok(())
+}
+
+fn load_object(md: &str, sha: &str) -> Result<Vec<u8>, Error> {
+    read_file_bytes(&format!("{}/objects/{}", md, sha))
+}
+
+fn load_metadata(md: &str) -> Result<Metadata, Error> {
+    let conf = format!("{}/index.json", md);
+    if !Path::new(&conf).exists() {
+        return Err(Error::WorkingCopy(format!("No clone metadata at {}; clone the project again", md)));
+    }
+    json::decode(&load_common_configuration(&conf)?)
+    .map_err(|e| Error::WorkingCopy(format!("Clone metadata at {} is damaged ({})", md, e)))
+}
+
+fn write_metadata(md: &str, meta: &Metadata) -> Result<(), Error> {
+    fs::create_dir_all(md).map_err(|e| Error::from_io(md, e))?;
+    let o = json::encode(meta)?;
+    write_common_configuration(&format!("{}/index.json", md), &o)
+}
+
+fn list_working_files(root: &str, dir: &str, files: &mut Vec<String>) -> Result<(), Error> {
+    let path = format!("{}/{}", root, dir);
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(&path).map_err(|e| Error::from_io(&path, e))? {
+        entries.push(entry.map_err(|e| Error::from_io(&path, e))?);
+    }
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if dir.is_empty() && is_reserved_path(&name) {
+            continue;
+        }
+        let rel = if dir.is_empty() { name } else { format!("{}/{}", dir, name) };
+        if entry.file_type().map_err(|e| Error::from_io(&rel, e))?.is_dir() {
+            list_working_files(root, &rel, files)?;
+        } else {
+            files.push(rel);
+        }
+    }
+    Ok(())
+}
+
+fn get_changes(wd: &str, meta: &Metadata) -> Result<Vec<(char, String)>, Error> {
+    let mut files: Vec<String> = Vec::new();
+    list_working_files(&wd, "", &mut files)?;
+    let matcher = match meta.get_filter() {
+        Some(f) => Some(f.matcher().map_err(Error::Config)?),
+        None => None,
+    };
+    let mut changes: Vec<(char, String)> = Vec::new();
+    for file in &files {
+        match meta.find(file) {
+            Some(entry) if !entry.get_local().is_empty() => {
+                let sha = git_blob_sha(&read_file_bytes(&format!("{}/{}", wd, file))?);
+                if sha != entry.get_local() {
+                    changes.push(('M', file.clone()));
+                }
+            },
+            // Files a sparse clone left out are not offered for commit.
+            _ if matcher.as_ref().map(|m| !m.is_match(file)).unwrap_or(false) => {},
+            _ => changes.push(('?', file.clone())),
+        }
+    }
+    let listed: BTreeSet<&str> = files.iter().map(|f| &f[..]).collect();
+    for entry in meta.get_entries() {
+        let path = entry.get_path();
+        if !entry.get_local().is_empty() && !listed.contains(&path[..]) && !is_reserved_path(&path) {
+            changes.push(('D', path));
+        }
+    }
+    changes.sort_by(|a, b| a.1.cmp(&b.1));
+    Ok(changes)
+}
+
+fn api_request(client: &Client, method: &str, url: &str, body: &str) -> Result<(u32, Json), Error> {
+    let (code, data) = client.request(method, url, body)?;
+    let res = Json::from_str(&String::from_utf8_lossy(&data)).unwrap_or(Json::Null);
+    Ok((code, res))
+}
+
+fn get_json_str(j: &Json, key: &str) -> String {
+    match j.find(key).and_then(|v| v.as_string()) {
+        Some(v) => v.to_owned(),
+        None => String::new(),
+    }
+}
+
+// A SHA GitHub should have sent back; a reply without one is a bad response.
+// SHAs end up in file names under .ghwcli/objects, so only real ones are taken.
+fn get_response_sha(res: &Json, path: &[&str], action: &str, code: u32) -> Result<String, Error> {
+    match res.find_path(path).and_then(|v| v.as_string()) {
+        Some(sha) if sha.len() == 40 && sha.chars().all(|c| c.is_digit(16)) => Ok(sha.to_owned()),
+        Some(sha) if !sha.is_empty() => {
+            Err(Error::Http(code, format!("Could not {} (bad SHA '{}')", action, sha)))
+        },
+        _ => Err(Error::Http(code, format!("Could not {} (no SHA in the response)", action))),
+    }
+}
+
+// Tree paths are joined onto the working copy, so none may lead out of it.
+fn is_safe_path(path: &str) -> bool {
+    !path.contains('\\') && !path.contains('\0') && path.split('/').all(|s| !s.is_empty() && s != "." && s != "..")
+}
+
+fn get_api_error(action: &str, code: u32, res: &Json) -> Error {
+    let message = match get_json_str(res, "message") {
+        ref m if m.is_empty() => "no details given".to_owned(),
+        m => m,
+    };
+    match code {
+        401 => Error::Auth(format!("Could not {} ({}); check your token", action, message)),
+        403 if !message.contains("rate limit") => {
+            Error::Auth(format!("Could not {} ({}); check your token's permissions", action, message))
+        },
+        _ => Error::Http(code, format!("Could not {} ({})", action, message)),
+    }
+}
+
+fn get_remote_head(gh: &GitHub, client: &Client, project: &Project) -> Result<String, Error> {
+    let url = format!("{}{}/git/ref/heads/{}",
+    gh.get_api_frag(), project.get_full_name(), project.get_branch());
+    let (code, res) = api_request(&client, "GET", &url, "")?;
+    if code != 200 {
+        return Err(get_api_error("get branch head", code, &res));
+    }
+    get_response_sha(&res, &["object", "sha"], "get branch head", code)
+}
+
+// Find the commit a tag or revision points at; annotated tags name a tag object
+// that has to be read to get to the commit.
+fn get_remote_commit(gh: &GitHub, client: &Client, project: &Project) -> Result<String, Error> {
+    if let Some(rev) = project.get_rev() {
+        let url = format!("{}{}/commits/{}", gh.get_api_frag(), project.get_full_name(), rev);
+        let (code, res) = api_request(&client, "GET", &url, "")?;
+        if code != 200 {
+            return Err(get_api_error(&format!("find revision {}", rev), code, &res));
+        }
+        return get_response_sha(&res, &["sha"], &format!("find revision {}", rev), code);
+    }
+    let tag = match project.get_tag() {
+        Some(tag) => tag,
+        None => return get_remote_head(&gh, &client, &project),
+    };
+    let url = format!("{}{}/git/ref/tags/{}", gh.get_api_frag(), project.get_full_name(), tag);
+    let (code, mut res) = api_request(&client, "GET", &url, "")?;
+    if code != 200 {
+        return Err(get_api_error(&format!("find tag {}", tag), code, &res));
+    }
+    while res.find_path(&["object", "type"]).and_then(|v| v.as_string()) == Some("tag") {
+        let sha = get_response_sha(&res, &["object", "sha"], &format!("read tag {}", tag), code)?;
+        let url = format!("{}{}/git/tags/{}", gh.get_api_frag(), project.get_full_name(), sha);
+        let (code, next) = api_request(&client, "GET", &url, "")?;
+        if code != 200 {
+            return Err(get_api_error(&format!("read tag {}", tag), code, &next));
+        }
+        res = next;
+    }
+    get_response_sha(&res, &["object", "sha"], &format!("find tag {}", tag), code)
+}
+
+fn get_default_branch(gh: &GitHub, client: &Client, name: &str) -> Result<String, Error> {
+    let url = format!("{}{}", gh.get_api_frag(), name);
+    let (code, res) = api_request(&client, "GET", &url, "")?;
+    match get_json_str(&res, "default_branch") {
+        ref branch if code == 200 && !branch.is_empty() => Ok(branch.clone()),
+        _ => Err(get_api_error("get repository", code, &res)),
+    }
+}
+
+// Work out whether a name given as owner/repo@ref is a branch, a tag or a revision.
+fn get_ref_kind(gh: &GitHub, client: &Client, name: &str, reference: &str) -> Result<&'static str, Error> {
+    for &(kind, prefix) in [("branch", "heads"), ("tag", "tags")].iter() {
+        let url = format!("{}{}/git/ref/{}/{}", gh.get_api_frag(), name, prefix, reference);
+        let (code, res) = api_request(&client, "GET", &url, "")?;
+        match code {
+            200 if res.find("object").is_some() => return Ok(kind),
+            200 | 404 => continue,
+            _ => return Err(get_api_error(&format!("look up {}", reference), code, &res)),
+        }
+    }
+    Ok("rev")
+}
+
+// Settle which ref the project is on: a ref given as owner/repo@ref, or else the
+// repository's default branch when no branch, tag or revision has been set.
+fn resolve_project_ref(gh: &GitHub, client: &Client, config: &mut Config, reference: &str) -> Result<(), Error> {
+    let name = format!("{}/{}", get_project_owner(config), config.get_string("name").unwrap_or(String::new()));
+    if !reference.is_empty() {
+        let kind = get_ref_kind(&gh, &client, &name, reference)?;
+        config.set(kind, Json::String(reference.to_owned()), "command line");
+    }
+    let is_unset = |v: Option<String>| v.map(|v| v.is_empty()).unwrap_or(true);
+    if is_unset(config.get_string("branch")) {
+        let branch = if is_unset(config.get_string("tag")) && is_unset(config.get_string("rev")) {
+            get_default_branch(&gh, &client, &name)?
+        } else {
+            String::new()
+        };
+        config.set("branch", Json::String(branch), "GitHub default branch");
+    }
+    Ok(())
+}
+
+fn check_on_branch(project: &Project) -> Result<(), Error> {
+    if !project.is_branch() {
+        return Err(Error::WorkingCopy(format!(
+            "{} is not a branch; clone a branch to commit, push and pull", project.get_ref())));
+    }
+    Ok(())
+}
+
+fn check_conflicts(meta: &Metadata) -> Result<(), Error> {
+    let conflicts = meta.get_conflicts();
+    if !conflicts.is_empty() {
+        return Err(Error::WorkingCopy(format!(
+            "Pull left conflicts in {}; fix them and run resolve first", conflicts.join(", "))));
+    }
+    Ok(())
+}
+
+fn get_commit_tree(gh: &GitHub, client: &Client, project: &Project, sha: &str) -> Result<String, Error> {
+    let url = format!("{}{}/git/commits/{}", gh.get_api_frag(), project.get_full_name(), sha);
+    let (code, res) = api_request(&client, "GET", &url, "")?;
+    if code != 200 {
+        return Err(get_api_error("get commit", code, &res));
+    }
+    get_response_sha(&res, &["tree", "sha"], "get commit", code)
+}
+
+// List every blob and tree under a tree by path. A listing too big for GitHub to
+// send in one go comes back truncated, and is then walked one directory at a time.
+fn get_remote_tree(gh: &GitHub, client: &Client, project: &Project, tree: &str)
+-> Result<BTreeMap<String, (String, String)>, Error> {
+    let mut items: BTreeMap<String, (String, String)> = BTreeMap::new();
+    let (code, res) = get_tree_listing(&gh, &client, &project, tree, true)?;
+    if res.find("truncated").and_then(|t| t.as_boolean()) != Some(true) {
+        add_tree_entries(&res, code, "", &mut items)?;
+        return Ok(items);
+    }
+    let mut pending = vec![(String::new(), tree.to_owned())];
+    while let Some((prefix, sha)) = pending.pop() {
+        let (code, res) = get_tree_listing(&gh, &client, &project, &sha, false)?;
+        if res.find("truncated").and_then(|t| t.as_boolean()) == Some(true) {
+            return Err(Error::Http(code, format!("Could not get tree ({} has too many entries)",
+            if prefix.is_empty() { "the top directory" } else { prefix.trim_right_matches('/') })));
+        }
+        for (path, sha) in add_tree_entries(&res, code, &prefix, &mut items)? {
+            pending.push((format!("{}/", path), sha));
+        }
+    }
+    Ok(items)
+}
+
+fn get_tree_listing(gh: &GitHub, client: &Client, project: &Project, tree: &str, recursive: bool)
+-> Result<(u32, Json), Error> {
+    let url = format!("{}{}/git/trees/{}{}", gh.get_api_frag(), project.get_full_name(), tree,
+    if recursive { "?recursive=1" } else { "" });
+    let (code, res) = api_request(&client, "GET", &url, "")?;
+    if code != 200 {
+        return Err(get_api_error("get tree", code, &res));
+    }
+    Ok((code, res))
+}
+
+// Add the entries of a tree listing under the prefix, giving back the subtrees found.
+fn add_tree_entries(res: &Json, code: u32, prefix: &str, items: &mut BTreeMap<String, (String, String)>)
+-> Result<Vec<(String, String)>, Error> {
+    let mut subtrees = Vec::new();
+    if let Some(entries) = res.find("tree").and_then(|t| t.as_array()) {
+        for entry in entries {
+            let kind = get_json_str(entry, "type");
+            if kind == "blob" || kind == "tree" {
+                let sha = get_response_sha(entry, &["sha"], "get tree", code)?;
+                let path = format!("{}{}", prefix, get_json_str(entry, "path"));
+                if !is_safe_path(&path) {
+                    return Err(Error::Http(code, format!("Could not get tree (unsafe path '{}')", path)));
+                }
+                if kind == "tree" {
+                    subtrees.push((path.clone(), sha.clone()));
+                }
+                items.insert(path, (get_json_str(entry, "mode"), sha));
+            }
+        }
+    }
+    Ok(subtrees)
+}
+
+fn create_git_object(gh: &GitHub, client: &Client, project: &Project, kind: &str, o: BTreeMap<String, Json>)
+-> Result<String, Error> {
+    let url = format!("{}{}/git/{}", gh.get_api_frag(), project.get_full_name(), kind);
+    let (code, res) = api_request(&client, "POST", &url, &Json::Object(o).to_string())?;
+    if code != 201 {
+        return Err(get_api_error(&format!("create {}", kind), code, &res));
+    }
+    get_response_sha(&res, &["sha"], &format!("create {}", kind), code)
+}
+
+fn create_blob(gh: &GitHub, client: &Client, project: &Project, md: &str, content: &[u8])
+-> Result<String, Error> {
+    let mut blob = BTreeMap::new();
+    blob.insert("content".to_owned(), Json::String(content.to_base64(STANDARD)));
+    blob.insert("encoding".to_owned(), Json::String("base64".to_owned()));
+    let sha = create_git_object(&gh, &client, &project, "blobs", blob)?;
+    store_object(md, &sha, content)?;
+    Ok(sha)
+}
+
+// An entry for a tree update, where an empty SHA deletes the file.
+fn get_tree_entry(file: &str, mode: &str, sha: &str) -> Json {
+    let mut entry = BTreeMap::new();
+    entry.insert("path".to_owned(), Json::String(file.to_owned()));
+    entry.insert("mode".to_owned(), Json::String(mode.to_owned()));
+    entry.insert("type".to_owned(), Json::String("blob".to_owned()));
+    entry.insert("sha".to_owned(), if sha.is_empty() { Json::Null } else { Json::String(sha.to_owned()) });
+    Json::Object(entry)
+}
+
+fn load_pending_commits(meta: &str) -> Result<Vec<PendingCommit>, Error> {
+    let conf = format!("{}/commits.json", meta);
+    if !Path::new(&conf).exists() {
+        return Ok(Vec::new());
+    }
+    json::decode(&load_common_configuration(&conf)?)
+    .map_err(|e| Error::WorkingCopy(format!("Local commits at {} are damaged ({})", conf, e)))
+}
+
+fn write_pending_commits(meta: &str, commits: &Vec<PendingCommit>) -> Result<(), Error> {
+    fs::create_dir_all(meta).map_err(|e| Error::from_io(meta, e))?;
+    let o = json::encode(commits)?;
+    write_common_configuration(&format!("{}/commits.json", meta), &o)
+}
+
+fn commit_changes(gh: &GitHub, client: &Client, project: &Project, wd: &str, message: &str, verbose: bool)
+-> Result<(), Error> {
+    check_on_branch(&project)?;
+    let md = get_meta_dir(wd);
+    let mut meta = load_metadata(&md)?;
+    check_conflicts(&meta)?;
+    let mut pending = load_pending_commits(&md)?;
+    let (parent, base_tree) = match pending.last() {
+        Some(pc) => (pc.get_sha(), pc.get_tree()),
+        None => {
+            let tree = get_commit_tree(&gh, &client, &project, &meta.get_commit())?;
+            (meta.get_commit(), tree)
+        }
+    };
+    let changes = get_changes(&wd, &meta)?;
+    if changes.is_empty() {
         if verbose {
-            println!("Retrieving index: {}{}", gh.get_index_frag(), project.get_index_frag());
+            println!("Nothing to commit.");
         }
-    } else if index == 2 {
-        //println!("Retrieving subindex: {}{}{}", gh.get_index_frag(), project.get_tree_frag(), file);
-        c.url(&format!("{}{}{}", gh.get_index_frag(), project.get_tree_frag(), file)).unwrap();
-    } else {
-        c.url(&format!("{}{}", gh.get_base_url(), file)).unwrap();
+        return Ok(());
     }
-    let pw = "_git_";
-    let mut out = format!("{}/{}", pw, file);
-    if index == 2 {
-        out = format!("{}/index.html", pw);
+    let remote = get_remote_tree(&gh, &client, &project, &base_tree)?;
+
+    let mut entries: Vec<Json> = Vec::new();
+    let mut updates: Vec<(String, String)> = Vec::new();
+    for &(status, ref file) in &changes {
+        let mode = match remote.get(file) {
+            Some(&(ref mode, _)) => mode.clone(),
+            None => "100644".to_owned(),
+        };
+        let sha = if status == 'D' {
+            String::new()
+        } else {
+            let content = read_file_bytes(&format!("{}/{}", wd, file))?;
+            create_blob(&gh, &client, &project, &md, &content)?
+        };
+        entries.push(get_tree_entry(file, &mode, &sha));
+        updates.push((file.clone(), sha));
+        if verbose {
+            println!("{} {}", if status == '?' { 'A' } else { status }, file);
+        }
     }
-    let p = split_path_from_file(&out);
-    if !Path::new(&p).exists() {
-        let _ = fs::create_dir_all(p);
+
+    let mut tree = BTreeMap::new();
+    tree.insert("base_tree".to_owned(), Json::String(base_tree));
+    tree.insert("tree".to_owned(), Json::Array(entries));
+    let tree_sha = create_git_object(&gh, &client, &project, "trees", tree)?;
+
+    let mut commit = BTreeMap::new();
+    commit.insert("message".to_owned(), Json::String(message.to_owned()));
+    commit.insert("tree".to_owned(), Json::String(tree_sha.clone()));
+    commit.insert("parents".to_owned(), Json::Array(vec![Json::String(parent.clone())]));
+    let sha = create_git_object(&gh, &client, &project, "commits", commit)?;
+
+    for (file, local) in updates {
+        let mut entry = match meta.find(&file) {
+            Some(entry) => entry.clone(),
+            None => Entry::new(&file, "", ""),
+        };
+        entry.set_local(&local);
+        meta.add_entry(entry);
     }
-    let mut w = File::create(&out).unwrap();
-    c.write_function(move |data| {
-        Ok(w.write(data).unwrap())
-    }).unwrap();
-    c.perform().unwrap();
-    if c.response_code().unwrap() != 200 {
-        let _ = fs::remove_file(&out);
+    meta.retain(|e| !e.get_sha().is_empty() || !e.get_local().is_empty());
+    write_metadata(&md, &meta)?;
+    pending.push(PendingCommit::new(&sha, &tree_sha, &parent, message));
+    write_pending_commits(&md, &pending)?;
+    if verbose {
+        println!("[{} {}] {}", project.get_branch(), &sha[..7], message);
+        println!("{} file(s) changed, {} commit(s) ready to push.", changes.len(), pending.len());
     }
-    if verbose && index == 0 {
-        println!("Retrieved file: {}{} [{}]", 
-        gh.get_base_url(), file, c.response_code().unwrap());
+    Ok(())
+}
+
+fn push_commits(gh: &GitHub, client: &Client, project: &Project, wd: &str, force: bool, verbose: bool)
+-> Result<(), Error> {
+    check_on_branch(&project)?;
+    let md = get_meta_dir(wd);
+    let pending = load_pending_commits(&md)?;
+    if pending.is_empty() {
+        if verbose {
+            println!("Nothing to push.");
+        }
+        return Ok(());
+    }
+    let head = get_remote_head(&gh, &client, &project)?;
+    let base = pending[0].get_parent();
+    if head != base && !force {
+        return Err(Error::WorkingCopy(format!(
+            "{} has moved from {} to {} on GitHub; refusing non-fast-forward push (use --force)",
+            project.get_branch(), &base[..7], &head[..7])));
+    }
+    let sha = pending[pending.len() - 1].get_sha();
+    let mut o = BTreeMap::new();
+    o.insert("sha".to_owned(), Json::String(sha.clone()));
+    o.insert("force".to_owned(), Json::Boolean(force));
+    let url = format!("{}{}/git/refs/heads/{}",
+    gh.get_api_frag(), project.get_full_name(), project.get_branch());
+    let (code, res) = api_request(&client, "PATCH", &url, &Json::Object(o).to_string())?;
+    if code != 200 {
+        return Err(get_api_error("update branch", code, &res));
+    }
+    let mut meta = load_metadata(&md)?;
+    meta.set_commit(&sha);
+    for entry in meta.get_entries_mut() {
+        let local = entry.get_local();
+        if !local.is_empty() && local != entry.get_sha() {
+            entry.set_remote(&get_content_hash(&load_object(&md, &local)?), &local);
+        }
+    }
+    meta.retain(|e| !e.get_local().is_empty());
+    write_metadata(&md, &meta)?;
+    let _ = fs::remove_file(format!("{}/commits.json", md));
+    if verbose {
+        for pc in &pending {
+            println!("{} {}", &pc.get_sha()[..7], pc.get_message());
+        }
+        println!("{}{}..{} {} -> {}", if force { "+" } else { " " },
+        &head[..7], &sha[..7], project.get_branch(), project.get_branch());
+        println!("Branch {} is now at {}.", project.get_branch(), sha);
     }
+    Ok(())
 }
 
-fn get_index(gh: &GitHub, project: &Project, verbose: bool, dindex: u32, file: &str) -> Vec<String> {
-    retrieve_file(&gh, &project, file, verbose, dindex);
-    let index = "_git_/index.html";
-    let mut f = File::open(&index).unwrap();
-    let mut html = String::new();
-    let _ = f.read_to_string(&mut html);
-    let mut links: Vec<String> = Vec::new();
-    for node in Document::from_str(&html).find(Name("a")).iter() {
-        links.push(node.attr("href").unwrap().to_owned());
+// The files in a tree on GitHub, by path, with their modes and blob SHAs.
+fn get_tree_files(gh: &GitHub, client: &Client, project: &Project, tree: &str)
+-> Result<BTreeMap<String, (String, String)>, Error> {
+    Ok(get_remote_tree(&gh, &client, &project, tree)?.into_iter()
+    .filter(|&(_, (ref mode, _))| mode != "040000").collect())
+}
+
+// Merge a file changed both in a local commit and on GitHub, or None when the
+// changes overlap, the file is binary or one side deleted it.
+fn merge_blobs(md: &str, wd: &str, file: &str, orig: &str, ours: &str, theirs: &str) -> Option<String> {
+    if ours.is_empty() || theirs.is_empty() {
+        return None;
     }
-    //let _ = fs::remove_file(&index); // !!!
-    links
+    let load = |sha: &str| match sha {
+        "" => Some(Vec::new()),
+        sha => load_object(md, sha).ok().or_else(|| get_local_blob(wd, file, sha)),
+    };
+    let (orig, ours, theirs) = (load(orig)?, load(ours)?, load(theirs)?);
+    let (text, conflicted) = merge3(str::from_utf8(&orig).ok()?, str::from_utf8(&ours).ok()?,
+    str::from_utf8(&theirs).ok()?);
+    if conflicted { None } else { Some(text) }
 }
 
-fn get_tree(gh: &GitHub, project: &Project, verbose: bool) -> Vec<String> {
-    let links: Vec<String> = get_index(&gh, &project, verbose, 1, "index.html");
-    let mut tree: Vec<String> = Vec::new();
-    for link in &links {
-        let p = Regex::new(&format!("/tree/{}", &project.get_branch())).unwrap();
-        if p.is_match(&link) {
-            tree.push(split_dir_from_tree(&link.clone()))
+// Make each unpushed commit again on top of the branch head, merging the files it
+// changed with what GitHub has. Replaying stops at the first commit that does not
+// merge cleanly; the commits made so far are given back with the files in the last
+// of them, and the file that stopped it with the number of commits left, if any.
+fn replay_commits(gh: &GitHub, client: &Client, project: &Project, wd: &str, pending: &[PendingCommit],
+head: &str, head_tree: &str)
+-> Result<(Vec<PendingCommit>, BTreeMap<String, (String, String)>, Option<(String, usize)>), Error> {
+    let md = get_meta_dir(wd);
+    let mut replayed: Vec<PendingCommit> = Vec::new();
+    if pending.is_empty() {
+        return Ok((replayed, BTreeMap::new(), None));
+    }
+    let mut files = get_tree_files(&gh, &client, &project, head_tree)?;
+    let base_tree = get_commit_tree(&gh, &client, &project, &pending[0].get_parent())?;
+    let mut before = get_tree_files(&gh, &client, &project, &base_tree)?;
+    let (mut parent, mut parent_tree) = (head.to_owned(), head_tree.to_owned());
+    for (i, pc) in pending.iter().enumerate() {
+        let after = get_tree_files(&gh, &client, &project, &pc.get_tree())?;
+        let mut paths: Vec<&String> = before.keys().chain(after.keys())
+        .filter(|&p| before.get(p) != after.get(p)).collect();
+        paths.sort();
+        paths.dedup();
+        let sha_in = |files: &BTreeMap<String, (String, String)>, path: &str| {
+            files.get(path).map(|&(_, ref sha)| sha.clone()).unwrap_or(String::new())
+        };
+        let mut updates: Vec<(String, String, String)> = Vec::new();
+        for path in paths {
+            let (orig, ours, theirs) = (sha_in(&before, path), sha_in(&after, path), sha_in(&files, path));
+            let sha = if theirs == orig || theirs == ours {
+                ours
+            } else {
+                match merge_blobs(&md, wd, path, &orig, &ours, &theirs) {
+                    Some(text) => create_blob(&gh, &client, &project, &md, text.as_bytes())?,
+                    None => return Ok((replayed, files, Some((path.clone(), pending.len() - i)))),
+                }
+            };
+            let mode = after.get(path).or(before.get(path)).map(|&(ref mode, _)| mode.clone()).unwrap();
+            updates.push((path.clone(), mode, sha));
+        }
+        before = after;
+        // A commit whose changes GitHub already has is left out.
+        if updates.iter().all(|&(ref path, _, ref sha)| sha_in(&files, path) == *sha) {
+            continue;
         }
+        let mut tree = BTreeMap::new();
+        tree.insert("base_tree".to_owned(), Json::String(parent_tree.clone()));
+        tree.insert("tree".to_owned(), Json::Array(updates.iter()
+        .map(|&(ref path, ref mode, ref sha)| get_tree_entry(path, mode, sha)).collect()));
+        let tree_sha = create_git_object(&gh, &client, &project, "trees", tree)?;
+        let mut commit = BTreeMap::new();
+        commit.insert("message".to_owned(), Json::String(pc.get_message()));
+        commit.insert("tree".to_owned(), Json::String(tree_sha.clone()));
+        commit.insert("parents".to_owned(), Json::Array(vec![Json::String(parent.clone())]));
+        let sha = create_git_object(&gh, &client, &project, "commits", commit)?;
+        for (path, mode, blob) in updates {
+            if blob.is_empty() {
+                files.remove(&path);
+            } else {
+                files.insert(path, (mode, blob));
+            }
+        }
+        replayed.push(PendingCommit::new(&sha, &tree_sha, &parent, &pc.get_message()));
+        parent = sha;
+        parent_tree = tree_sha;
     }
-    println!("Tree is: {:?}", tree); // !
-    tree
+    Ok((replayed, files, None))
 }
 
-fn get_files(gh: &GitHub, project: &Project, verbose: bool, dindex: u32, file: &str) 
--> Vec<String> {
-    let links: Vec<String> = get_index(&gh, &project, verbose, dindex, &file);
-    let mut files: Vec<String> = Vec::new();
-    for link in &links {
-        let mut p = Regex::new("https://").unwrap();
-        if p.is_match(&link) {
+// Bring the working copy up to the branch head: files only changed on GitHub are
+// replaced, files changed on both sides are merged, and unpushed commits are made
+// again on top. A commit that does not merge cleanly, and any after it, are folded
+// back into local changes so they can be committed again once fixed.
+fn pull_changes(gh: &GitHub, client: &Client, project: &Project, wd: &str, jobs: usize, verbose: bool)
+-> Result<bool, Error> {
+    check_on_branch(&project)?;
+    let md = get_meta_dir(wd);
+    let mut meta = load_metadata(&md)?;
+    check_conflicts(&meta)?;
+    let pending = load_pending_commits(&md)?;
+    let head = get_remote_head(&gh, &client, &project)?;
+    let base = meta.get_commit();
+    if head == base {
+        if verbose {
+            println!("Already up to date.");
+        }
+        return Ok(false);
+    }
+    let tree = get_commit_tree(&gh, &client, &project, &head)?;
+    let matcher = match meta.get_filter() {
+        Some(f) => Some(f.matcher().map_err(Error::Config)?),
+        None => None,
+    };
+    let depth = meta.get_depth();
+    let mut remote: BTreeMap<String, String> = BTreeMap::new();
+    for (file, (mode, sha)) in get_remote_tree(&gh, &client, &project, &tree)? {
+        let skipped = (depth > 0 && get_path_depth(&file) > depth) || is_reserved_path(&file);
+        if mode == "040000" || skipped {
             continue;
         }
-        p = Regex::new("/blob/").unwrap();
-        if p.is_match(&link) {
-            files.push(split_url_from_blob(&link));
+        if matcher.as_ref().map(|m| m.is_match(&file)).unwrap_or(true) {
+            remote.insert(file, sha);
+        }
+    }
+    let mut files: Vec<String> = remote.keys().cloned().collect();
+    for entry in meta.get_entries() {
+        let path = entry.get_path();
+        if !entry.get_sha().is_empty() && !remote.contains_key(&path) && !is_reserved_path(&path) {
+            files.push(path);
         }
     }
-    files
+    files.sort();
+
+    // Work out what each file needs before fetching anything.
+    let mut fetches: Vec<(String, String)> = Vec::new();
+    let mut deletes: Vec<String> = Vec::new();
+    let mut unchanged = 0;
+    for file in &files {
+        let theirs = remote.get(file).cloned().unwrap_or(String::new());
+        let ours = match read_file_bytes(&format!("{}/{}", wd, file)) {
+            Ok(content) => git_blob_sha(&content),
+            Err(_) => String::new(),
+        };
+        let base_sha = meta.find(file).map(|e| e.get_sha()).unwrap_or(String::new());
+        if theirs == base_sha || theirs == ours {
+            unchanged += 1;
+            continue;
+        }
+        if theirs.is_empty() {
+            deletes.push(file.clone());
+        } else if !Path::new(&format!("{}/objects/{}", md, theirs)).exists() {
+            fetches.push((file.clone(), theirs));
+        }
+    }
+    if verbose {
+        println!("Updating {}..{}", &base[..7], &head[..7]);
+    }
+    let urls: Vec<String> = fetches.iter()
+    .map(|&(ref file, _)| get_file_url(&gh, &project, &head, file)).collect();
+    let fetched = urls.len();
+    for ((file, sha), response) in fetches.into_iter().zip(client.request_all(&urls, jobs)?) {
+        let (code, content) = response?;
+        if code != 200 {
+            return Err(get_fetch_error(code, &content).with_message(&format!("Could not fetch {}", file)));
+        }
+        store_object(&md, &sha, &content)?;
+    }
+    let (replayed, committed, stuck) = replay_commits(&gh, &client, &project, wd, &pending, &head, &tree)?;
+
+    let (mut updated, mut merged, mut conflicts) = (0, 0, 0);
+    for file in &files {
+        let theirs = remote.get(file).cloned().unwrap_or(String::new());
+        let base_sha = meta.find(file).map(|e| e.get_sha()).unwrap_or(String::new());
+        if theirs == base_sha {
+            continue;
+        }
+        let path = format!("{}/{}", wd, file);
+        let local = read_file_bytes(&path).ok();
+        let ours = local.as_ref().map(|c| git_blob_sha(c)).unwrap_or(String::new());
+        if theirs != ours {
+            let status = if ours == base_sha {
+                // Unchanged here, so GitHub's version simply replaces it.
+                if theirs.is_empty() {
+                    fs::remove_file(&path).map_err(|e| Error::from_io(&path, e))?;
+                    'D'
+                } else {
+                    write_working_file(wd, file, &load_object(&md, &theirs)?)?;
+                    if base_sha.is_empty() { 'A' } else { 'U' }
+                }
+            } else if theirs.is_empty() || local.is_none() {
+                // Deleted on one side and changed on the other: keep whichever still has content.
+                if local.is_none() {
+                    write_working_file(wd, file, &load_object(&md, &theirs)?)?;
+                }
+                'C'
+            } else {
+                let orig = match base_sha.as_ref() {
+                    "" => Vec::new(),
+                    sha => load_object(&md, sha).unwrap_or(Vec::new()),
+                };
+                let theirs_content = load_object(&md, &theirs)?;
+                let ours_content = local.clone().unwrap();
+                match (str::from_utf8(&orig), str::from_utf8(&ours_content), str::from_utf8(&theirs_content)) {
+                    (Ok(o), Ok(a), Ok(b)) => {
+                        let (text, conflicted) = merge3(o, a, b);
+                        write_working_file(wd, file, text.as_bytes())?;
+                        if conflicted { 'C' } else { 'M' }
+                    },
+                    // Binary files cannot be merged, so the local copy is left alone.
+                    _ => 'C',
+                }
+            };
+            match status {
+                'C' => {
+                    meta.add_conflict(file);
+                    conflicts += 1;
+                },
+                'M' => merged += 1,
+                _ => updated += 1,
+            }
+            if verbose || status == 'C' {
+                println!("{} {}", status, file);
+            }
+        }
+        if theirs.is_empty() {
+            meta.remove_entry(file);
+        } else {
+            let hash = get_content_hash(&load_object(&md, &theirs).unwrap_or(Vec::new()));
+            meta.add_entry(Entry::new(file, &hash, &theirs));
+        }
+    }
+    // What the replayed commits hold is committed; anything else is a local change.
+    for entry in meta.get_entries_mut() {
+        let local = match committed.get(&entry.get_path()) {
+            _ if replayed.is_empty() => entry.get_sha(),
+            Some(&(_, ref sha)) => sha.clone(),
+            None => String::new(),
+        };
+        entry.set_local(&local);
+    }
+    meta.retain(|e| !e.get_sha().is_empty() || !e.get_local().is_empty());
+    meta.set_commit(&head);
+    write_metadata(&md, &meta)?;
+    if replayed.is_empty() {
+        let _ = fs::remove_file(format!("{}/commits.json", md));
+    } else {
+        write_pending_commits(&md, &replayed)?;
+    }
+    if let Some((file, left)) = stuck {
+        eprintln!("Warning: {} unpushed commit(s) could not be replayed ({} changed on both sides); \
+        they are now local changes, commit them again to push.", left, file);
+    }
+    if verbose {
+        if !replayed.is_empty() {
+            println!("Replayed {} unpushed commit(s) on top of {}.", replayed.len(), &head[..7]);
+        }
+        println!("Fetched {} file(s), skipped {} unchanged.", fetched, unchanged);
+        println!("{} file(s) updated, {} merged, {} conflict(s).", updated, merged, conflicts);
+    }
+    if conflicts > 0 {
+        println!("Fix the conflicts marked with <<<<<<< and >>>>>>>, then resolve each file before committing.");
+    }
+    Ok(conflicts > 0)
+}
+
+fn get_status(wd: &str, meta: &Metadata) -> Result<Vec<(char, char, String)>, Error> {
+    let mut status: BTreeMap<String, (char, char)> = BTreeMap::new();
+    for entry in meta.get_entries() {
+        let (sha, local) = (entry.get_sha(), entry.get_local());
+        let x = if sha.is_empty() {
+            'A'
+        } else if local.is_empty() {
+            'D'
+        } else if sha != local {
+            'M'
+        } else {
+            continue;
+        };
+        status.insert(entry.get_path(), (x, ' '));
+    }
+    for (y, file) in get_changes(&wd, &meta)? {
+        if y == '?' {
+            status.insert(file, ('?', '?'));
+        } else {
+            let x = status.get(&file).map(|s| s.0).unwrap_or(' ');
+            status.insert(file, (x, y));
+        }
+    }
+    for file in meta.get_conflicts() {
+        status.insert(file, ('U', 'U'));
+    }
+    Ok(status.into_iter().map(|(file, (x, y))| (x, y, file)).collect())
+}
+
+fn resolve_conflicts(wd: &str, files: &[String], verbose: bool) -> Result<(), Error> {
+    let md = get_meta_dir(wd);
+    let mut meta = load_metadata(&md)?;
+    // Files are named relative to where resolve is run from, like everywhere else.
+    let cwd = env::current_dir().map_err(|e| Error::from_io(".", e))?;
+    let prefix = cwd.strip_prefix(wd).map(|p| p.to_string_lossy().replace("\\", "/")).unwrap_or(String::new());
+    for name in files {
+        let mut parts: Vec<&str> = Vec::new();
+        let joined = format!("{}/{}", prefix, name.replace("\\", "/"));
+        for part in joined.split('/') {
+            match part {
+                "" | "." => {},
+                ".." => {
+                    parts.pop();
+                },
+                p => parts.push(p),
+            }
+        }
+        let file = parts.join("/");
+        if let Ok(content) = read_file_bytes(&format!("{}/{}", wd, file)) {
+            let text = String::from_utf8_lossy(&content);
+            if text.lines().any(|l| l == "<<<<<<< local" || l == ">>>>>>> remote") {
+                return Err(Error::WorkingCopy(format!("{} still has conflict markers", file)));
+            }
+        }
+        if !meta.resolve_conflict(&file) {
+            return Err(Error::WorkingCopy(format!("{} has no conflict to resolve", file)));
+        }
+        if verbose {
+            println!("Resolved {}", file);
+        }
+    }
+    write_metadata(&md, &meta)
+}
+
+fn display_status(project: &Project, wd: &str, porcelain: bool, verbose: bool) -> Result<(), Error> {
+    let md = get_meta_dir(wd);
+    let meta = load_metadata(&md)?;
+    let status = get_status(&wd, &meta)?;
+    if porcelain {
+        for (x, y, file) in status {
+            println!("{}{} {}", x, y, file);
+        }
+        return Ok(());
+    }
+    if !verbose {
+        return Ok(());
+    }
+    if project.is_branch() {
+        println!("On branch {}", project.get_branch());
+    } else {
+        println!("Not on a branch; cloned at {}", project.get_ref());
+    }
+    if meta.get_depth() > 0 {
+        println!("Cloned to a depth of {} directory level(s).", meta.get_depth());
+    }
+    if let Some(filter) = meta.get_filter() {
+        println!("Sparse clone of {}.", filter.describe());
+    }
+    let pending = load_pending_commits(&md)?;
+    if !pending.is_empty() {
+        println!("Your branch is ahead of GitHub by {} commit(s); use push to publish.", pending.len());
+    }
+    if status.is_empty() {
+        println!("Nothing to commit, working copy clean.");
+    }
+    for (x, y, file) in status {
+        println!("{}{} {}", x, y, file);
+    }
+    Ok(())
 }
 
-fn retrieve_repo(gh: &GitHub, project: &Project, verbose: bool) {
-    let tree: Vec<String> = get_tree(&gh, &project, verbose);
-    for branch in &tree {
-        let files = get_files(&gh, &project, verbose, 2, &branch);
-        for file in files {
-            retrieve_file(&gh, &project, &file, verbose, 0);
+fn check_for_diff(orig: &[u8], edit: &[u8], old_name: &str, new_name: &str, verbose: bool) -> bool {
+    if orig == edit && old_name != "/dev/null" && new_name != "/dev/null" {
+        return false;
+    }
+    if verbose {
+        match (str::from_utf8(orig), str::from_utf8(edit)) {
+            (Ok(o), Ok(e)) if !o.contains('\0') && !e.contains('\0') => {
+                print!("{}", unified_diff(o, e, old_name, new_name));
+            },
+            _ => println!("Binary files {} and {} differ", old_name, new_name),
         }
     }
+    true
 }
 
-fn check_for_diff(orig: &str, edit: &str) {
-    let (dist, changeset) = diff(orig, edit, "");
-    println!("dist: {:?}", dist);
-    println!("cs: {:?}", changeset);
+fn diff_working_copy(wd: &str, verbose: bool) -> Result<bool, Error> {
+    let md = get_meta_dir(wd);
+    let meta = load_metadata(&md)?;
+    let mut differs = false;
+    for (status, file) in get_changes(&wd, &meta)? {
+        let (orig, old_name) = match meta.find(&file) {
+            Some(entry) if status != '?' => (load_object(&md, &entry.get_local())?, format!("a/{}", file)),
+            _ => (Vec::new(), "/dev/null".to_owned()),
+        };
+        let (edit, new_name) = match status {
+            'D' => (Vec::new(), "/dev/null".to_owned()),
+            _ => (read_file_bytes(&format!("{}/{}", wd, file))?, format!("b/{}", file)),
+        };
+        if check_for_diff(&orig, &edit, &old_name, &new_name, verbose) {
+            differs = true;
+        }
+    }
+    Ok(differs)
 }
 
-fn write_common_configuration(conf: &str, o: &str) {
-    let mut w = File::create(conf).unwrap();
+fn write_common_configuration(conf: &str, o: &str) -> Result<(), Error> {
+    let mut w = File::create(conf).map_err(|e| Error::from_io(conf, e))?;
     let fo = format!("{}\n", o);
-    let _ = w.write_all(fo.as_bytes());
+    w.write_all(fo.as_bytes()).map_err(|e| Error::from_io(conf, e))
 }
 
-fn get_input(prompt: &str) -> String {
+fn get_input(prompt: &str) -> Result<String, Error> {
     println!("{}? ", prompt);
     let mut input = String::new();
-    match stdin().read_line(&mut input) {
-        Ok(_) => {},
-        Err(error) => {
-            println!("Stdin Error: {}", error);
-            exit(-1);
-        }
+    stdin().read_line(&mut input).map_err(|e| Error::from_io("stdin", e))?;
+    Ok(input.trim().to_owned())
+}
+
+fn get_secret(prompt: &str) -> Result<String, Error> {
+    if unsafe { libc::isatty(0) } == 0 {
+        return get_input(prompt);
+    }
+    let secret = rpassword::prompt_password_stdout(&format!("{}? ", prompt))
+    .map_err(|e| Error::from_io("stdin", e))?;
+    Ok(secret.trim().to_owned())
+}
+
+fn read_stdin_secret() -> Result<String, Error> {
+    let mut input = String::new();
+    stdin().read_to_string(&mut input).map_err(|e| Error::from_io("stdin", e))?;
+    if input.trim().is_empty() {
+        return Err(Error::Config("No token was given on stdin".to_owned()));
     }
-    input.trim().to_owned()
+    Ok(input.trim().to_owned())
 }
 
-fn write_gh_configuration(conf: &str) {
-    let username = get_input("Username");
-    let password = get_input("Password");
-    let gh = GitHub::new(&username, &password);
-    let o = json::encode(&gh).unwrap();
-    write_common_configuration(conf, &o);
+// The object holding the settings of a profile, or the top level when no profile is named.
+fn get_section<'a>(o: &'a mut Json, profile: &str) -> Option<&'a mut json::Object> {
+    let fields = match o.as_object_mut() {
+        Some(fields) => fields,
+        None => return None,
+    };
+    if profile.is_empty() {
+        return Some(fields);
+    }
+    let profiles = fields.entry("profiles".to_owned()).or_insert(Json::Object(BTreeMap::new()));
+    match profiles.as_object_mut() {
+        Some(profiles) => {
+            profiles.entry(profile.to_owned()).or_insert(Json::Object(BTreeMap::new())).as_object_mut()
+        },
+        None => None,
+    }
 }
 
-fn write_project_configuration(conf: &str) {
-    let name = get_input("Project name");
-    let branch = get_input("Branch");
-    let project = Project::new(&name, &branch);
-    let o = json::encode(&project).unwrap();
-    write_common_configuration(conf, &o);
+// Secrets kept beside a working copy could be committed along with it.
+fn check_secret_destination(conf: &str) -> Result<(), Error> {
+    let dir = Path::new(conf).parent().unwrap_or(Path::new(""));
+    if dir.join(".ghwcli").join("index.json").exists() {
+        return Err(Error::WorkingCopy(format!("Refusing to write a secret to {} inside a working copy; \
+        use --global or a credential helper", conf)));
+    }
+    Ok(())
 }
 
-fn load_common_configuration(conf: &str) -> String {
+fn write_gh_configuration(conf: &str, profile: &str, helper: &str, username: &str, web_url: &str,
+token_stdin: bool) -> Result<(), Error> {
+    if helper.is_empty() {
+        check_secret_destination(conf)?;
+    }
+    let username = if username.is_empty() { get_input("Username")? } else { username.to_owned() };
+    let token = if token_stdin { read_stdin_secret()? } else { get_secret("Personal access token")? };
+    let mut gh = GitHub::new(&username);
+    if !web_url.is_empty() {
+        gh.set_web_url(web_url);
+    }
+    if helper.is_empty() {
+        gh.set_token(&token);
+    } else {
+        let ch = CredentialHelper::new(helper);
+        let _ = ch.erase(&gh.get_host(), &username);
+        if !ch.store(&gh.get_host(), &username, &token) {
+            return Err(Error::Config(format!("Could not store token with credential helper '{}'", helper)));
+        }
+        gh.set_credential_helper(helper);
+    }
+    let account = Json::from_str(&json::encode(&gh)?)?;
+    let mut o = load_json_configuration(conf)?;
+    if let Some(p) = Path::new(conf).parent() {
+        let _ = fs::create_dir_all(p);
+    }
+    {
+        let section = match get_section(&mut o, profile) {
+            Some(section) => section,
+            None => return Err(Error::Config(format!("{} has no room for profile '{}'", conf, profile))),
+        };
+        for key in ACCOUNT_KEYS {
+            section.remove(*key);
+        }
+        if let Some(fields) = account.as_object() {
+            for (k, v) in fields.iter().filter(|&(_, v)| !v.is_null()) {
+                section.insert(k.clone(), v.clone());
+            }
+        }
+    }
+    write_common_configuration(conf, &o.to_string())
+}
+
+fn write_project_configuration(conf: &str) -> Result<(), Error> {
+    let owner = get_input("Owner (blank for your own account)")?;
+    let name = get_input("Project name")?;
+    let branch = get_input("Branch (blank for the repository default)")?;
+    let mut o = BTreeMap::new();
+    if !owner.is_empty() {
+        o.insert("owner".to_owned(), Json::String(owner));
+    }
+    o.insert("name".to_owned(), Json::String(name));
+    if !branch.is_empty() {
+        o.insert("branch".to_owned(), Json::String(branch));
+    }
+    write_common_configuration(conf, &Json::Object(o).to_string())
+}
+
+fn load_common_configuration(conf: &str) -> Result<String, Error> {
     let mut lines = String::new();
-    let mut file = File::open(conf).unwrap();
-    let _ = file.read_to_string(&mut lines);
-    lines
+    let mut file = File::open(conf).map_err(|e| Error::from_io(conf, e))?;
+    file.read_to_string(&mut lines).map_err(|e| Error::from_io(conf, e))?;
+    Ok(lines)
+}
+
+fn get_user_configuration() -> String {
+    let base = match (env::var("XDG_CONFIG_HOME"), env::var("HOME"), env::var("APPDATA")) {
+        (Ok(ref xdg), _, _) if !xdg.is_empty() => xdg.clone(),
+        (_, Ok(ref home), _) if !home.is_empty() => format!("{}/.config", home),
+        (_, _, Ok(ref appdata)) if !appdata.is_empty() => appdata.clone(),
+        _ => ".".to_owned(),
+    };
+    format!("{}/ghwcli/config.json", base)
+}
+
+fn load_json_configuration(conf: &str) -> Result<Json, Error> {
+    if !Path::new(conf).exists() {
+        return Ok(Json::Object(BTreeMap::new()));
+    }
+    let o = Json::from_str(&load_common_configuration(&conf)?)
+    .map_err(|e| Error::Config(format!("{} is not valid JSON ({})", conf, e)))?;
+    if !o.is_object() {
+        return Err(Error::Config(format!("{} must hold a JSON object", conf)));
+    }
+    Ok(o)
 }
 
-fn load_gh_configuration(conf: &str) -> GitHub {
-    let ghj = Json::from_str(&load_common_configuration(&conf)).unwrap();
-    json::decode(&ghj.to_string()).unwrap()
+fn get_env_configuration() -> Json {
+    let vars = [
+        ("GHWCLI_USERNAME", "username"), ("GITHUB_TOKEN", "token"),
+        ("GHWCLI_TOKEN_FILE", "token_file"), ("GHWCLI_CREDENTIAL_HELPER", "credential_helper"),
+        ("GHWCLI_WEB_URL", "web_url"), ("GHWCLI_API_URL", "api_url"), ("GHWCLI_RAW_URL", "raw_url"),
+        ("GHWCLI_PROJECT", "name"), ("GHWCLI_BRANCH", "branch"),
+        ("GHWCLI_PROXY_PASSWORD", "proxy_password"), ("GHWCLI_PROFILE", "profile"),
+    ];
+    let mut o = BTreeMap::new();
+    for &(var, key) in vars.iter() {
+        if let Ok(value) = env::var(var) {
+            if !value.trim().is_empty() {
+                o.insert(key.to_owned(), Json::String(value.trim().to_owned()));
+            }
+        }
+    }
+    Json::Object(o)
+}
+
+// Settings layer from the user file up to the command line. When a profile is
+// selected at any layer, its sections from every file sit above the files'
+// own settings, and the working copy's own project sits above those, but both
+// stay below the environment and the command line.
+fn load_configuration(ghconf: &str, prjconf: &str, copy: &(String, Json), flags: &Json)
+-> Result<Config, Error> {
+    let user = get_user_configuration();
+    let files = vec![
+        (user.clone(), load_json_configuration(&user)?),
+        (ghconf.to_owned(), load_json_configuration(ghconf)?),
+        (prjconf.to_owned(), load_json_configuration(prjconf)?),
+    ];
+    let env = get_env_configuration();
+    let mut config = Config::new();
+    for &(ref origin, ref o) in &files {
+        config.merge(origin, o);
+    }
+    let selected = flags.find("profile").or(env.find("profile")).or(copy.1.find("profile"))
+    .and_then(|p| p.as_string()).map(|p| p.to_owned()).or(config.get_string("profile"));
+    if let Some(name) = selected {
+        let mut found = false;
+        for &(ref origin, ref o) in &files {
+            if let Some(section) = o.find_path(&["profiles", &name]) {
+                config.merge(&format!("{} [{}]", origin, name), section);
+                found = true;
+            }
+        }
+        if !found {
+            return Err(Error::Config(format!("No profile named '{}' in {}, {} or {}",
+            name, user, ghconf, prjconf)));
+        }
+    }
+    if !copy.0.is_empty() {
+        config.merge(&get_meta_dir(&copy.0), &copy.1);
+    }
+    config.merge("environment", &env);
+    config.merge("command line", flags);
+    Ok(config)
+}
+
+// The host the account settings name, before a working copy or the command
+// line points ghwcli somewhere else.
+fn get_account_host(ghconf: &str, prjconf: &str, copy: &(String, Json), flags: &Json) -> Result<String, Error> {
+    let only_profile = |o: &Json| {
+        let mut p = BTreeMap::new();
+        if let Some(profile) = o.find("profile") {
+            p.insert("profile".to_owned(), profile.clone());
+        }
+        Json::Object(p)
+    };
+    let bare = (String::new(), only_profile(&copy.1));
+    let config = load_configuration(ghconf, prjconf, &bare, &only_profile(flags))?;
+    let mut account = GitHub::new("");
+    if let Some(url) = config.get_string("web_url") {
+        account.set_web_url(&url);
+    }
+    Ok(account.get_host())
+}
+
+// A host named in <repo> has to be the one the account is set up for, so that
+// a URL alone never sends the configured token somewhere else.
+fn check_spec_host(repo: &str, url: &str, account_host: &str) -> Result<(), Error> {
+    let mut spec = GitHub::new("");
+    spec.set_web_url(url);
+    if spec.get_host() != account_host {
+        return Err(Error::Config(format!("{} is on {}, but the account is set up for {}; \
+        select a --profile for {} or give --web-url", repo, spec.get_host(), account_host, spec.get_host())));
+    }
+    Ok(())
+}
+
+fn load_gh_configuration(config: &Config) -> Result<GitHub, Error> {
+    let mut account = config.to_json(ACCOUNT_KEYS);
+    if let Json::Object(ref mut o) = account {
+        o.entry("username".to_owned()).or_insert(Json::String(String::new()));
+    }
+    json::decode(&account.to_string())
+    .map_err(|e| Error::Config(format!("Invalid account settings ({})", e)))
+}
+
+// Projects set up before owners were configured apart belong to the account.
+fn get_project_owner(config: &Config) -> String {
+    match config.get_string("owner") {
+        Some(ref owner) if !owner.is_empty() => owner.clone(),
+        _ => config.get_string("username").unwrap_or(String::new()),
+    }
+}
+
+fn load_token(gh: &mut GitHub) -> Result<(), Error> {
+    if gh.get_token().is_some() {
+        return Ok(());
+    }
+    if let Some(helper) = gh.get_credential_helper() {
+        match CredentialHelper::new(&helper).get(&gh.get_host(), &gh.get_username()) {
+            Some(token) => {
+                gh.set_token(&token);
+                return Ok(());
+            },
+            None => eprintln!("Warning: Credential helper '{}' has no token for {}@{}.",
+            helper, gh.get_username(), gh.get_host()),
+        }
+    }
+    if let Some(path) = gh.get_token_file() {
+        if !Path::new(&path).exists() {
+            return Err(Error::Config(format!("Token file {} does not exist", path)));
+        }
+        let token = load_common_configuration(&path)?;
+        gh.set_token(token.trim());
+    } else if gh.get_password().is_some() {
+        eprintln!("Warning: Password authentication is deprecated; run configure to set a token.");
+    }
+    Ok(())
+}
+
+fn load_project_configuration(config: &Config) -> Result<Project, Error> {
+    let mut project: Project = json::decode(&config.to_json(PROJECT_KEYS).to_string())
+    .map_err(|e| Error::Config(format!("Invalid project settings ({})", e)))?;
+    project.set_owner(&get_project_owner(config));
+    Ok(project)
+}
+
+fn load_network_configuration(client: &mut Client, config: &Config) {
+    client.set_proxy(config.get_string("proxy"), config.get_string("proxy_username"),
+    config.get_string("proxy_password"));
+    client.set_no_proxy(config.get_string("no_proxy"));
+    client.set_ca_bundle(config.get_string("ca_bundle"));
+    let insecure = config.get_string("insecure").map(|v| v == "true").unwrap_or(false);
+    if insecure {
+        eprintln!("Warning: TLS certificate checks are off; only use --insecure on a network you trust.");
+    }
+    client.set_insecure(insecure);
+}
+
+fn run_config_command(program: &str, config: &Config, action: &str, key: &str, value: &str,
+global: bool, profile: &str, ghconf: &str, prjconf: &str) -> Result<(), Error> {
+    match action {
+        "list" => {
+            for (k, v, origin) in config.list() {
+                let shown = match v {
+                    _ if SECRET_KEYS.contains(&k.as_str()) => "********".to_owned(),
+                    Json::String(ref s) => s.clone(),
+                    _ => v.to_string(),
+                };
+                println!("{}={} ({})", k, shown, origin);
+            }
+        },
+        "get" => {
+            match config.get(key) {
+                Some(&Json::String(ref s)) => println!("{}", s),
+                Some(v) => println!("{}", v),
+                None => exit(1),
+            }
+        },
+        "set" => {
+            if !Config::is_key(key) || value.is_empty() {
+                display_error(program, &format!("Cannot set '{}'; usage is config set <key> <value>", key));
+            }
+            let user = get_user_configuration();
+            let shared = key == "profile" || PROJECT_KEYS.contains(&key);
+            let conf = if global {
+                user.as_str()
+            } else if shared {
+                prjconf
+            } else {
+                ghconf
+            };
+            if SECRET_KEYS.contains(&key) && !global {
+                check_secret_destination(conf)?;
+            }
+            let mut o = load_json_configuration(conf)?;
+            match get_section(&mut o, if shared { "" } else { profile }) {
+                Some(section) => section.insert(key.to_owned(), Json::String(value.to_owned())),
+                None => return Err(Error::Config(format!("{} has no room for profile '{}'", conf, profile))),
+            };
+            if let Some(p) = Path::new(conf).parent() {
+                let _ = fs::create_dir_all(p);
+            }
+            write_common_configuration(conf, &o.to_string())?;
+        },
+        _ => display_error(program, "Unknown config action; use get, set or list"),
+    }
+    Ok(())
 }
 
-fn load_project_configuration(conf: &str) -> Project {
-    let prj = Json::from_str(&load_common_configuration(&conf)).unwrap();
-    json::decode(&prj.to_string()).unwrap()
+fn exit_on_error<T>(result: Result<T, Error>) -> T {
+    match result {
+        Ok(value) => value,
+        Err(err) => {
+            eprintln!("Error: {}.", err);
+            exit(err.get_code());
+        }
+    }
 }
 
 fn display_version() {
@@ -220,7 +1543,7 @@ fn display_version() {
 }
 
 fn display_error(program: &str, err: &str) {
-    println!("Error: {}.\n", err);
+    eprintln!("Error: {}.\n", err);
     display_usage(program, -1);
 }
 
@@ -231,77 +1554,404 @@ fn display_usage(program: &str, code: i32) {
     println!("\nReleased under the MIT License.");
     println!("\nUsage: {} <command> [<repo>][<options>]", program);
     println!("\nCommands:\n");
-    println!("clone : Clone the configured project or at specified GitHub repo.");
+    println!("clone : Clone the configured project or <repo> into [<dest>] (default ./<repo>).");
+    println!("        <repo> is owner/repo[@ref], a GitHub URL (including /tree/<ref>/<path> links),");
+    println!("        git@host:owner/repo.git or ssh://git@host/owner/repo.git.");
+    println!("        Cloning again over a clean working copy only fetches files whose blob changed.");
     println!("diff : See the differences between working directory and GitHub repo.");
     println!("commit : Commit the local changes back to the GitHub repo.");
     println!("push : Push the local changes back to the GitHub repo.");
+    println!("config : Get, set or list configuration values (config get|set|list [<key>] [<value>]).");
+    println!("pull | update : Bring the working copy up to date with its branch on GitHub, merging local edits.");
+    println!("resolve : Mark <file>s that pull left conflicted as fixed so they can be committed.");
+    println!("status : Show modified (M), added (A), deleted (D), conflicted (UU) and untracked (?) files.");
     println!("\nOptions:\n");
     println!("-h | --help : Display this usage information and exit.");
     println!("-v | --version : Display program version and exit.");
     println!("-q | --quiet : Do not output non-error messages to stdout.");
+    println!("-m | --message : Message to use for commit.");
+    println!("-f | --force : Allow push to overwrite a branch that is not a fast-forward.");
+    println!("--porcelain : Give status output in a stable, easy-to-parse format.");
+    println!("--stats : Print request counts and transfer timings to stderr when done.");
+    println!("--depth : Limit clone to N directory levels (0 for no limit).");
+    println!("-b | --branch : Clone this branch instead of the repository default.");
+    println!("--tag : Clone the commit at this tag (read-only).");
+    println!("--rev : Clone this commit SHA (read-only).");
+    println!("--path : Clone only this directory or file of the repository.");
+    println!("--include : Clone only files matching this glob (repeatable, e.g. '*.md').");
+    println!("--exclude : Leave out files matching this glob on clone (repeatable, e.g. 'vendor/**').");
+    println!("--keep-going : Finish a clone even when some files cannot be fetched.");
+    println!("-j | --jobs : Download up to N files at once on clone (default 8).");
+    println!("--retries : Retry failed or rate-limited requests up to N times (default 3).");
+    println!("--timeout : Give up on a request after N seconds (default 0 for no limit).");
+    println!("--token-file : Read the GitHub access token from this file.");
+    println!("--global : Make configure and config set write to the user-level configuration file.");
+    println!("--profile : Use the named account profile (also for configure and config set).");
+    println!("--username : Username to configure without prompting.");
+    println!("--token-stdin : Read the token for configure from stdin without prompting.");
+    println!("--credential-helper : Keep the token in this git credential helper on configure.");
+    println!("--keyring : Keep the token in the Secret Service keyring on configure.");
+    println!("--web-url : Web URL of the GitHub host (e.g. https://ghe.example.com for Enterprise).");
+    println!("--api-url : Use another GitHub API base URL (default follows --web-url).");
+    println!("--raw-url : Use another base URL for raw file contents (default follows --web-url).");
+    println!("--proxy : Send every request through this HTTP(S) proxy URL.");
+    println!("--proxy-user : Username and optional password (user[:password]) for the proxy.");
+    println!("--no-proxy : Comma-separated hosts to reach without the proxy.");
+    println!("--cacert : Verify servers against the CA certificates in this bundle file.");
+    println!("--insecure : Skip TLS certificate checks (not recommended).");
+    println!("\nExit codes:\n");
+    println!("0 : Success.");
+    println!("1 : diff found differences, config get found no value or pull left conflicts.");
+    println!("2 : Network error.");
+    println!("3 : Unexpected HTTP status from GitHub.");
+    println!("4 : Authentication failed.");
+    println!("5 : Configuration error.");
+    println!("6 : File I/O error.");
+    println!("7 : Working copy missing or not in a state to do this.");
+    println!("255 : Invalid command line usage.");
     exit(code);
 }
 
+// Options that take the next argument as their value.
+const VALUE_OPTIONS: [&'static str; 25] = ["-m", "--message", "--web-url", "--api-url", "--raw-url", "--proxy",
+"--proxy-user", "--no-proxy", "--cacert", "--token-file", "--credential-helper", "--username", "--profile",
+"-b", "--branch", "--tag", "--rev", "--path", "--include", "--exclude", "-j", "--jobs", "--retries", "--timeout",
+"--depth"];
+
+// The arguments following a command, up to the first option.
+fn get_operands(cli: &CliOptions, i: usize, max: usize) -> Vec<String> {
+    (i..i + max).map(|j| cli.next_argument(j)).take_while(|a| !a.is_empty() && !a.starts_with("-")).collect()
+}
+
 fn main() {
     let cli = CliOptions::new("ghwcli");
     let program = cli.get_program();
 
     // ---------------------------------
-    let ghconf = ".github.json";
-    let prjconf = ".project.json";
+    let located = find_working_copy();
+    let root = located.as_ref().ok().cloned();
+    let ghconf = get_config_path(&root, CONFIG_FILES[0]);
+    let prjconf = get_config_path(&root, CONFIG_FILES[1]);
     // ---------------------------------
 
-    let mut gh: GitHub = GitHub::new("u", "p");
-    let mut project: Project = Project::new("n", "b");
     let mut repo = String::new();
+    let mut dest = String::new();
+    let mut message = String::new();
     let mut verbose = true;
+    let mut force = false;
+    let mut porcelain = false;
+    let mut web_url = String::new();
+    let mut api_url = String::new();
+    let mut raw_url = String::new();
+    let mut depth = 0;
+    let mut keep_going = false;
+    let mut stats = false;
+    let mut jobs = 8;
+    let mut retries = 3;
+    let mut network: BTreeMap<String, Json> = BTreeMap::new();
+    let mut timeout = 0;
+    let mut token_file = String::new();
+    let mut helper = String::new();
+    let mut username = String::new();
+    let mut token_stdin = false;
+    let mut global = false;
+    let mut profile = String::new();
+    let mut branch = String::new();
+    let mut tag = String::new();
+    let mut rev = String::new();
+    let mut reference = String::new();
+    let mut path = String::new();
+    let mut include: Vec<String> = Vec::new();
+    let mut exclude: Vec<String> = Vec::new();
+    let mut action = String::new();
+    let mut key = String::new();
+    let mut value = String::new();
+    let mut files: Vec<String> = Vec::new();
     let mut op = -1;
 
     if cli.get_num() > 1 {
+        let mut skip = 0;
         for (i, a) in cli.get_args().iter().enumerate() {
+            // Values taken by the option or command before this are not read again.
+            if skip > 0 {
+                skip -= 1;
+                continue;
+            }
+            if VALUE_OPTIONS.contains(&a.trim()) {
+                skip = 1;
+            }
             match a.trim() {
                 "-h" | "--help" => display_usage(&program, 0),
                 "-v" | "--version" => display_version(),
                 "-q" | "--quiet" => verbose = false,
                 "clone" => {
                     op = 0;
-                    repo = cli.next_argument(i);
+                    let operands = get_operands(&cli, i, 2);
+                    skip = operands.len();
+                    repo = operands.get(0).cloned().unwrap_or(String::new());
+                    dest = operands.get(1).cloned().unwrap_or(String::new());
                 },
                 "configure" => op = 1,
+                "commit" => op = 2,
+                "push" => op = 3,
+                "diff" => op = 4,
+                "status" => op = 5,
+                "pull" | "update" => op = 7,
+                "resolve" => {
+                    op = 8;
+                    files = get_operands(&cli, i, cli.get_args().len());
+                    skip = files.len();
+                },
+                "config" => {
+                    op = 6;
+                    let operands = get_operands(&cli, i, 3);
+                    skip = operands.len();
+                    action = operands.get(0).cloned().unwrap_or(String::new());
+                    key = operands.get(1).cloned().unwrap_or(String::new());
+                    value = operands.get(2).cloned().unwrap_or(String::new());
+                },
+                "-m" | "--message" => message = cli.next_argument(i),
+                "-f" | "--force" => force = true,
+                "--porcelain" => porcelain = true,
+                "--stats" => stats = true,
+                "--web-url" => web_url = cli.next_argument(i),
+                "--api-url" => api_url = cli.next_argument(i),
+                "--raw-url" => raw_url = cli.next_argument(i),
+                "--proxy" => {
+                    network.insert("proxy".to_owned(), Json::String(cli.next_argument(i)));
+                },
+                "--proxy-user" => {
+                    let user = cli.next_argument(i);
+                    let mut parts = user.splitn(2, ':');
+                    if let Some(username) = parts.next() {
+                        network.insert("proxy_username".to_owned(), Json::String(username.to_owned()));
+                    }
+                    if let Some(password) = parts.next() {
+                        network.insert("proxy_password".to_owned(), Json::String(password.to_owned()));
+                    }
+                },
+                "--no-proxy" => {
+                    network.insert("no_proxy".to_owned(), Json::String(cli.next_argument(i)));
+                },
+                "--cacert" => {
+                    network.insert("ca_bundle".to_owned(), Json::String(cli.next_argument(i)));
+                },
+                "--insecure" => {
+                    network.insert("insecure".to_owned(), Json::Boolean(true));
+                },
+                "--token-file" => token_file = cli.next_argument(i),
+                "--credential-helper" => helper = cli.next_argument(i),
+                "--keyring" => helper = "keyring".to_owned(),
+                "--username" => username = cli.next_argument(i),
+                "--token-stdin" => token_stdin = true,
+                "--global" => global = true,
+                "--profile" => profile = cli.next_argument(i),
+                "-b" | "--branch" => branch = cli.next_argument(i),
+                "--tag" => tag = cli.next_argument(i),
+                "--rev" => rev = cli.next_argument(i),
+                "--path" => {
+                    if !path.is_empty() {
+                        display_error(&program, "Give --path only once");
+                    }
+                    path = cli.next_argument(i);
+                },
+                "--include" => include.push(cli.next_argument(i)),
+                "--exclude" => exclude.push(cli.next_argument(i)),
+                "--keep-going" => keep_going = true,
+                "-j" | "--jobs" => {
+                    jobs = match cli.next_argument(i).parse::<usize>() {
+                        Ok(j) if j > 0 => j,
+                        _ => {
+                            display_error(&program, "Jobs must be a whole number above zero");
+                            0
+                        }
+                    };
+                },
+                "--retries" => {
+                    retries = match cli.next_argument(i).parse::<u32>() {
+                        Ok(r) => r,
+                        Err(_) => {
+                            display_error(&program, "Retries must be a whole number");
+                            0
+                        }
+                    };
+                },
+                "--timeout" => {
+                    timeout = match cli.next_argument(i).parse::<u64>() {
+                        Ok(t) => t,
+                        Err(_) => {
+                            display_error(&program, "Timeout must be a whole number of seconds");
+                            0
+                        }
+                    };
+                },
+                "--depth" => {
+                    depth = match cli.next_argument(i).parse::<u32>() {
+                        Ok(d) => d,
+                        Err(_) => {
+                            display_error(&program, "Depth must be a whole number");
+                            0
+                        }
+                    };
+                },
                 _ => continue,
             }
         }
     } else {
         display_error(&program, "No options provided");
     }
-    
-    if repo.is_empty() {
-        if !Path::new(ghconf).exists() {
-            write_gh_configuration(ghconf)
+
+    if op == 1 {
+        if token_stdin && username.is_empty() {
+            display_error(&program, "--token-stdin requires --username");
         }
+        let conf = if global { get_user_configuration() } else { ghconf.clone() };
+        exit_on_error(write_gh_configuration(&conf, &profile, &helper, &username, &web_url, token_stdin));
+        if !token_stdin {
+            exit_on_error(write_project_configuration(&prjconf));
+        }
+        return;
+    }
 
-        if !Path::new(prjconf).exists() {
-            write_project_configuration(prjconf);
+    let mut flags = BTreeMap::new();
+    let mut spec_url = None;
+    if !repo.is_empty() {
+        let remote = match Remote::parse(&repo) {
+            Ok(remote) => remote,
+            Err(msg) => {
+                display_error(&program, &msg);
+                return;
+            }
+        };
+        flags.insert("owner".to_owned(), Json::String(remote.get_owner()));
+        flags.insert("name".to_owned(), Json::String(remote.get_repo()));
+        if let Some(r) = remote.get_reference() {
+            reference = r;
         }
+        if let Some(p) = remote.get_path() {
+            if !path.is_empty() {
+                display_error(&program, "Give only one of a path in <repo> and --path");
+            }
+            path = p;
+        }
+        if web_url.is_empty() {
+            spec_url = remote.get_web_url();
+        }
+    }
+    // Check the globs now rather than after the first requests have gone out.
+    let filter = Filter::new(&path, include, exclude);
+    if let Err(msg) = filter.matcher() {
+        display_error(&program, &msg);
+    }
+    if [&branch, &tag, &rev].iter().filter(|r| !r.is_empty()).count() + (!reference.is_empty() as usize) > 1 {
+        display_error(&program, "Give only one of a ref in <repo>, --branch, --tag and --rev");
+    }
+    if !repo.is_empty() || !branch.is_empty() || !tag.is_empty() || !rev.is_empty() {
+        flags.insert("branch".to_owned(), Json::String(branch));
+        flags.insert("tag".to_owned(), Json::String(tag));
+        flags.insert("rev".to_owned(), Json::String(rev));
+    }
+    if !web_url.is_empty() {
+        flags.insert("web_url".to_owned(), Json::String(web_url.clone()));
+    }
+    if !api_url.is_empty() {
+        flags.insert("api_url".to_owned(), Json::String(api_url));
+    }
+    if !raw_url.is_empty() {
+        flags.insert("raw_url".to_owned(), Json::String(raw_url));
+    }
+    if !token_file.is_empty() {
+        flags.insert("token_file".to_owned(), Json::String(token_file));
+    }
+    flags.append(&mut network);
+    if !profile.is_empty() && !(op == 6 && action == "set") {
+        flags.insert("profile".to_owned(), Json::String(profile.clone()));
+    }
+    let flags = Json::Object(flags);
+    let copy = match (op, located) {
+        (0, _) | (6, Err(_)) => (String::new(), Json::Object(BTreeMap::new())),
+        (_, Ok(wd)) => {
+            let o = exit_on_error(get_copy_configuration(&wd));
+            (wd, o)
+        },
+        (_, Err(err)) => exit_on_error(Err(err)),
+    };
+    if op == 8 {
+        if files.is_empty() {
+            display_error(&program, "No files to resolve provided");
+        }
+        exit_on_error(resolve_conflicts(&copy.0, &files, verbose));
+        return;
+    }
+    let mut config = exit_on_error(load_configuration(&ghconf, &prjconf, &copy, &flags));
+    if op == 6 {
+        exit_on_error(run_config_command(&program, &config, &action, &key, &value, global, &profile,
+        &ghconf, &prjconf));
+        return;
+    }
 
-        gh = load_gh_configuration(ghconf);
-        project = load_project_configuration(prjconf);
+    // A repository named with its owner can be fetched without an account.
+    if !config.has("username") && !config.has("owner") {
+        exit_on_error(write_gh_configuration(&ghconf, &profile, &helper, "", &web_url, false));
+    }
+    if !config.has("name") {
+        exit_on_error(write_project_configuration(&prjconf));
+    }
+    config = exit_on_error(load_configuration(&ghconf, &prjconf, &copy, &flags));
+    let account_host = exit_on_error(get_account_host(&ghconf, &prjconf, &copy, &flags));
+    if let Some(url) = spec_url {
+        exit_on_error(check_spec_host(&repo, &url, &account_host));
     }
-    match op {
+    let mut gh = exit_on_error(load_gh_configuration(&config));
+    // Configured credentials only go to the host they were set up for; any other
+    // host gets a token from the credential helper or --token-file, or none.
+    let own_token_file = config.get_origin("token_file") == Some("command line".to_owned());
+    if gh.get_host() != account_host && !own_token_file && gh.clear_credentials() {
+        eprintln!("Warning: Not sending the credentials set up for {} to {}.", account_host, gh.get_host());
+    }
+    exit_on_error(load_token(&mut gh));
+    let mut client = Client::new(&gh);
+    client.set_retries(retries);
+    client.set_timeout(timeout);
+    client.set_verbose(verbose);
+    load_network_configuration(&mut client, &config);
+    exit_on_error(resolve_project_ref(&gh, &client, &mut config, &reference));
+    let project = exit_on_error(load_project_configuration(&config));
+    let wd = match op {
+        0 if dest.is_empty() => project.get_name(),
+        0 => dest.trim_right_matches('/').to_owned(),
+        _ => copy.0.clone(),
+    };
+    // diff finding differences and pull leaving conflicts exit with 1.
+    let unclean = match op {
         0 => {
-            if !repo.is_empty() {
-                let p = Regex::new(r"(\w+)/([\w-]+)").unwrap();
-                for cap in p.captures_iter(&repo) {
-                    gh = GitHub::new(&cap[1], "-");
-                    project = Project::new(&cap[2], "master");
-                }
+            exit_on_error(retrieve_repo(&gh, &client, &project, &wd, config.get_string("profile"), &filter, depth,
+            jobs, keep_going, verbose));
+            false
+        },
+        2 => {
+            if message.is_empty() {
+                display_error(&program, "No commit message provided");
             }
-            retrieve_repo(&gh, &project, verbose);
+            exit_on_error(commit_changes(&gh, &client, &project, &wd, &message, verbose));
+            false
         },
-        1 => {
-            write_gh_configuration(ghconf);
-            write_project_configuration(prjconf);
+        3 => {
+            exit_on_error(push_commits(&gh, &client, &project, &wd, force, verbose));
+            false
         },
-        _ => {}
+        4 => exit_on_error(diff_working_copy(&wd, verbose)),
+        5 => {
+            exit_on_error(display_status(&project, &wd, porcelain, verbose));
+            false
+        },
+        7 => exit_on_error(pull_changes(&gh, &client, &project, &wd, jobs, verbose)),
+        _ => false,
+    };
+    if stats {
+        client.display_stats();
+    }
+    if unclean {
+        exit(1);
     }
 }
@@ -0,0 +1,107 @@
+use unified::match_lines;
+
+// Lines are split on "\n" without dropping the last (possibly empty) piece,
+// so joining them again gives back the exact text, final newline or not.
+fn split_lines(text: &str) -> Vec<&str> {
+    text.split("\n").collect()
+}
+
+/// Merge the changes local and remote each made to base, diff3 style.
+/// Returns the merged text and whether any conflict markers were written.
+pub fn merge3(base: &str, local: &str, remote: &str) -> (String, bool) {
+    // Keep a shared final newline out of the way of conflict markers.
+    if local.ends_with("\n") && remote.ends_with("\n") {
+        let trim = |t: &str| if t.ends_with("\n") { t[..t.len() - 1].to_owned() } else { t.to_owned() };
+        let (merged, conflicts) = merge3(&trim(base), &trim(local), &trim(remote));
+        return (format!("{}\n", merged), conflicts);
+    }
+    let (o, a, b) = (split_lines(base), split_lines(local), split_lines(remote));
+    let (ma, mb) = (match_lines(&o, &a), match_lines(&o, &b));
+    let mut out: Vec<&str> = Vec::new();
+    let mut conflicts = false;
+    let (mut io, mut ia, mut ib) = (0, 0, 0);
+    loop {
+        // Copy the run of base lines both sides kept in step with each other.
+        let mut n = 0;
+        while ma.get(&(io + n)) == Some(&(ia + n)) && mb.get(&(io + n)) == Some(&(ib + n)) {
+            n += 1;
+        }
+        if n > 0 {
+            out.extend_from_slice(&o[io..io + n]);
+            io += n;
+            ia += n;
+            ib += n;
+            continue;
+        }
+        // Otherwise everything up to the next line both sides kept has changed.
+        let next = (io..o.len()).find(|k| ma.contains_key(k) && mb.contains_key(k));
+        let (eo, ea, eb) = match next {
+            Some(k) => (k, ma[&k], mb[&k]),
+            None => (o.len(), a.len(), b.len()),
+        };
+        let (co, ca, cb) = (&o[io..eo], &a[ia..ea], &b[ib..eb]);
+        if ca == co {
+            out.extend_from_slice(cb);
+        } else if cb == co || ca == cb {
+            out.extend_from_slice(ca);
+        } else {
+            conflicts = true;
+            out.push("<<<<<<< local");
+            out.extend_from_slice(ca);
+            out.push("=======");
+            out.extend_from_slice(cb);
+            out.push(">>>>>>> remote");
+        }
+        if next.is_none() {
+            break;
+        }
+        io = eo;
+        ia = ea;
+        ib = eb;
+    }
+    (out.join("\n"), conflicts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::merge3;
+
+    #[test]
+    fn changes_on_one_side_are_taken() {
+        let base = "a\nb\nc\n";
+        assert_eq!(merge3(base, base, "a\nB\nc\n"), ("a\nB\nc\n".to_owned(), false));
+        assert_eq!(merge3(base, "a\nB\nc\n", base), ("a\nB\nc\n".to_owned(), false));
+    }
+
+    #[test]
+    fn separate_changes_are_combined() {
+        let (text, conflicts) = merge3("a\nb\nc\nd\ne\n", "A\nb\nc\nd\ne\n", "a\nb\nc\nd\nE\nf\n");
+        assert_eq!((text.as_ref(), conflicts), ("A\nb\nc\nd\nE\nf\n", false));
+    }
+
+    #[test]
+    fn identical_changes_do_not_conflict() {
+        assert_eq!(merge3("a\nb\n", "a\nx\n", "a\nx\n"), ("a\nx\n".to_owned(), false));
+    }
+
+    #[test]
+    fn overlapping_changes_get_markers() {
+        let (text, conflicts) = merge3("a\nb\nc\n", "a\nlocal\nc\n", "a\nremote\nc\n");
+        assert!(conflicts);
+        assert_eq!(text, "a\n<<<<<<< local\nlocal\n=======\nremote\n>>>>>>> remote\nc\n");
+    }
+
+    #[test]
+    fn final_newline_is_kept_apart_from_markers() {
+        let (text, conflicts) = merge3("a", "b\n", "c\n");
+        assert!(conflicts);
+        assert_eq!(text, "<<<<<<< local\nb\n=======\nc\n>>>>>>> remote\n");
+        assert_eq!(merge3("a\nb", "a\nb\n", "a\nb"), ("a\nb\n".to_owned(), false));
+    }
+
+    #[test]
+    fn files_added_on_both_sides() {
+        assert_eq!(merge3("", "same\n", "same\n"), ("same\n".to_owned(), false));
+        assert!(merge3("", "one\n", "two\n").1);
+    }
+}
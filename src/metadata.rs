@@ -0,0 +1,162 @@
+use filter::Filter;
+use std::collections::{BTreeMap, BTreeSet};
+use std::collections::btree_map::{Values, ValuesMut};
+
+#[derive(Debug, Clone, RustcDecodable, RustcEncodable)]
+pub struct Entry {
+    path: String,
+    hash: String,
+    sha: String,
+    local: String,
+}
+
+impl Entry {
+    pub fn new(path: &str, hash: &str, sha: &str) -> Entry {
+        Entry {
+            path: path.to_owned(),
+            hash: hash.to_owned(),
+            sha: sha.to_owned(),
+            local: sha.to_owned(),
+        }
+    }
+    pub fn get_path(&self) -> String {
+        self.path.clone()
+    }
+    pub fn get_sha(&self) -> String {
+        self.sha.clone()
+    }
+    pub fn get_local(&self) -> String {
+        self.local.clone()
+    }
+    pub fn set_local(&mut self, local: &str) {
+        self.local = local.to_owned();
+    }
+    pub fn set_remote(&mut self, hash: &str, sha: &str) {
+        self.hash = hash.to_owned();
+        self.sha = sha.to_owned();
+    }
+}
+
+#[derive(Debug, RustcDecodable, RustcEncodable)]
+pub struct Metadata {
+    owner: String,
+    repo: String,
+    branch: String,
+    tag: Option<String>,
+    rev: Option<String>,
+    commit: String,
+    web_url: Option<String>,
+    api_url: Option<String>,
+    raw_url: Option<String>,
+    profile: Option<String>,
+    depth: u32,
+    filter: Option<Filter>,
+    conflicts: Option<BTreeSet<String>>,
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Metadata {
+    pub fn new(owner: &str, repo: &str, branch: &str, commit: &str) -> Metadata {
+        Metadata {
+            owner: owner.to_owned(),
+            repo: repo.to_owned(),
+            branch: branch.to_owned(),
+            tag: None,
+            rev: None,
+            commit: commit.to_owned(),
+            web_url: None,
+            api_url: None,
+            raw_url: None,
+            profile: None,
+            depth: 0,
+            filter: None,
+            conflicts: None,
+            entries: BTreeMap::new(),
+        }
+    }
+    pub fn get_owner(&self) -> String {
+        self.owner.clone()
+    }
+    pub fn get_repo(&self) -> String {
+        self.repo.clone()
+    }
+    pub fn get_branch(&self) -> String {
+        self.branch.clone()
+    }
+    pub fn get_tag(&self) -> Option<String> {
+        self.tag.clone()
+    }
+    pub fn get_rev(&self) -> Option<String> {
+        self.rev.clone()
+    }
+    pub fn set_ref(&mut self, tag: Option<String>, rev: Option<String>) {
+        self.tag = tag;
+        self.rev = rev;
+    }
+    pub fn get_commit(&self) -> String {
+        self.commit.clone()
+    }
+    pub fn set_commit(&mut self, commit: &str) {
+        self.commit = commit.to_owned();
+    }
+    // The host and profile a copy was cloned with, so later commands go back there.
+    pub fn get_hosts(&self) -> Vec<(&'static str, Option<String>)> {
+        vec![("web_url", self.web_url.clone()), ("api_url", self.api_url.clone()),
+        ("raw_url", self.raw_url.clone())]
+    }
+    pub fn set_hosts(&mut self, web_url: &str, api_url: &str, raw_url: &str) {
+        self.web_url = Some(web_url.to_owned());
+        self.api_url = Some(api_url.to_owned());
+        self.raw_url = Some(raw_url.to_owned());
+    }
+    pub fn get_profile(&self) -> Option<String> {
+        self.profile.clone()
+    }
+    pub fn set_profile(&mut self, profile: Option<String>) {
+        self.profile = profile;
+    }
+    pub fn get_depth(&self) -> u32 {
+        self.depth
+    }
+    pub fn set_depth(&mut self, depth: u32) {
+        self.depth = depth;
+    }
+    pub fn get_filter(&self) -> Option<Filter> {
+        self.filter.clone()
+    }
+    pub fn set_filter(&mut self, filter: &Filter) {
+        self.filter = if filter.is_empty() { None } else { Some(filter.clone()) };
+    }
+    // Files pull left conflicted; commit waits until each one is resolved.
+    pub fn get_conflicts(&self) -> Vec<String> {
+        self.conflicts.iter().flat_map(|c| c.iter().cloned()).collect()
+    }
+    pub fn add_conflict(&mut self, path: &str) {
+        self.conflicts.get_or_insert_with(BTreeSet::new).insert(path.to_owned());
+    }
+    pub fn resolve_conflict(&mut self, path: &str) -> bool {
+        let resolved = self.conflicts.as_mut().map(|c| c.remove(path)).unwrap_or(false);
+        if self.conflicts.as_ref().map(|c| c.is_empty()).unwrap_or(false) {
+            self.conflicts = None;
+        }
+        resolved
+    }
+    pub fn get_entries(&self) -> Values<String, Entry> {
+        self.entries.values()
+    }
+    pub fn get_entries_mut(&mut self) -> ValuesMut<String, Entry> {
+        self.entries.values_mut()
+    }
+    pub fn find(&self, path: &str) -> Option<&Entry> {
+        self.entries.get(path)
+    }
+    pub fn add_entry(&mut self, entry: Entry) {
+        self.entries.insert(entry.path.clone(), entry);
+    }
+    pub fn remove_entry(&mut self, path: &str) {
+        self.entries.remove(path);
+    }
+    pub fn retain<F: FnMut(&Entry) -> bool>(&mut self, mut keep: F) {
+        self.entries.retain(|_, e| keep(e));
+    }
+}
@@ -1,26 +1,53 @@
 #[derive(Debug, RustcDecodable, RustcEncodable)]
 pub struct Project {
+    owner: Option<String>,
     name: String,
     branch: String,
+    tag: Option<String>,
+    rev: Option<String>,
 }
 
 impl Project {
     pub fn new(name: &str, branch: &str) -> Project {
         Project {
+            owner: None,
             name: name.to_owned(),
             branch: branch.to_owned(),
+            tag: None,
+            rev: None,
         }
     }
     pub fn get_url_frag(&self) -> String {
         format!("{}/{}/", self.name, self.branch)
     }
-    pub fn get_tree_frag(&self) -> String {
-        format!("{}/tree/", self.name)
+    // The account or organization the repository belongs to, which need not be
+    // the account ghwcli signs in as.
+    pub fn get_owner(&self) -> String {
+        self.owner.clone().unwrap_or(String::new())
     }
-    pub fn get_index_frag(&self) -> String {
-        format!("{}", self.name)
+    pub fn set_owner(&mut self, owner: &str) {
+        self.owner = Some(owner.to_owned());
+    }
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+    pub fn get_full_name(&self) -> String {
+        format!("{}/{}", self.get_owner(), self.name)
     }
     pub fn get_branch(&self) -> String {
         self.branch.clone()
     }
+    // An empty tag or rev clears one set in a lower configuration layer.
+    pub fn get_tag(&self) -> Option<String> {
+        self.tag.clone().and_then(|t| if t.is_empty() { None } else { Some(t) })
+    }
+    pub fn get_rev(&self) -> Option<String> {
+        self.rev.clone().and_then(|r| if r.is_empty() { None } else { Some(r) })
+    }
+    pub fn is_branch(&self) -> bool {
+        self.get_tag().is_none() && self.get_rev().is_none()
+    }
+    pub fn get_ref(&self) -> String {
+        self.get_rev().or(self.get_tag()).unwrap_or(self.get_branch())
+    }
 }
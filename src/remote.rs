@@ -0,0 +1,157 @@
+use regex::Regex;
+
+/// A repository named on the command line, in any of the forms git or
+/// GitHub hand out: owner/repo[@ref], a web URL (optionally a /tree/ or
+/// /blob/ link), git@host:owner/repo.git or ssh://git@host/owner/repo.git.
+#[derive(Debug)]
+pub struct Remote {
+    web_url: Option<String>,
+    owner: String,
+    repo: String,
+    reference: Option<String>,
+    path: Option<String>,
+}
+
+impl Remote {
+    pub fn parse(spec: &str) -> Result<Remote, String> {
+        let spec = spec.trim();
+        let invalid = |why: &str| Err(format!("Invalid repository '{}': {}", spec, why));
+        let scp = Regex::new(r"^(?:[^@/:]+@)?([\w.-]+):(.+)$").unwrap();
+        let (web_url, rest) = if let Some(i) = spec.find("://") {
+            let scheme = &spec[..i];
+            let after = &spec[i + 3..];
+            let (authority, rest) = match after.find('/') {
+                Some(j) => (&after[..j], &after[j..]),
+                None => return invalid("expected owner/repo after the host"),
+            };
+            let host = authority.rsplit('@').next().unwrap();
+            let url = match scheme {
+                "http" | "https" => format!("{}://{}", scheme, host),
+                // SSH and git ports say nothing about where the web server listens.
+                "ssh" | "git" => format!("https://{}", host.split(':').next().unwrap()),
+                _ => return invalid(&format!("unsupported scheme {}://", scheme)),
+            };
+            (Some(url), rest)
+        } else if let Some(cap) = scp.captures(spec) {
+            (Some(format!("https://{}", &cap[1])), cap.get(2).unwrap().as_str())
+        } else if spec.split('/').next().map(|s| s.contains('.')).unwrap_or(false) {
+            // github.com/owner/repo, as copied without the scheme.
+            match spec.find('/') {
+                Some(j) => (Some(format!("https://{}", &spec[..j])), &spec[j..]),
+                None => return invalid("expected owner/repo, a GitHub URL or git@host:owner/repo.git"),
+            }
+        } else {
+            (None, spec)
+        };
+        let segments: Vec<&str> = rest.trim_matches('/').split('/').collect();
+        if segments.len() < 2 || segments[0].is_empty() || segments[1].is_empty() {
+            return invalid("expected owner/repo, a GitHub URL or git@host:owner/repo.git");
+        }
+        let owner = segments[0];
+        let (repo, mut reference) = match segments[1].find('@') {
+            Some(j) => (&segments[1][..j], Some(segments[1][j + 1..].to_owned())),
+            None => (segments[1], None),
+        };
+        let repo = if repo.ends_with(".git") { &repo[..repo.len() - 4] } else { repo };
+        let mut path = None;
+        if segments.len() > 2 {
+            // A branch name containing slashes cannot be told apart from the
+            // path here, so the first segment after /tree/ is taken as the ref.
+            if (segments[2] != "tree" && segments[2] != "blob") || segments.len() < 4 || reference.is_some() {
+                return invalid(&format!("unexpected '{}' after owner/repo", segments[2..].join("/")));
+            }
+            reference = Some(segments[3].to_owned());
+            if segments.len() > 4 {
+                path = Some(segments[4..].join("/"));
+            }
+        }
+        if !Regex::new(r"^[A-Za-z0-9](?:[A-Za-z0-9-]*[A-Za-z0-9])?$").unwrap().is_match(owner) {
+            return invalid(&format!("'{}' is not a valid owner name", owner));
+        }
+        if !Regex::new(r"^[\w.-]+$").unwrap().is_match(repo) || repo == "." || repo == ".." {
+            return invalid(&format!("'{}' is not a valid repository name", repo));
+        }
+        if reference.as_ref().map(|r| r.is_empty()).unwrap_or(false) {
+            return invalid("empty ref after '@'");
+        }
+        Ok(Remote {
+            web_url: web_url.map(|u| if u.ends_with("://github.com") || u.ends_with("://www.github.com") {
+                "https://github.com".to_owned()
+            } else {
+                u
+            }),
+            owner: owner.to_owned(),
+            repo: repo.to_owned(),
+            reference: reference,
+            path: path,
+        })
+    }
+    // Only set when the spec names a host.
+    pub fn get_web_url(&self) -> Option<String> {
+        self.web_url.clone()
+    }
+    pub fn get_owner(&self) -> String {
+        self.owner.clone()
+    }
+    pub fn get_repo(&self) -> String {
+        self.repo.clone()
+    }
+    pub fn get_reference(&self) -> Option<String> {
+        self.reference.clone()
+    }
+    pub fn get_path(&self) -> Option<String> {
+        self.path.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Remote;
+
+    fn parse(spec: &str) -> (Option<String>, String, String, Option<String>, Option<String>) {
+        let r = Remote::parse(spec).unwrap();
+        (r.get_web_url(), r.get_owner(), r.get_repo(), r.get_reference(), r.get_path())
+    }
+
+    fn some(s: &str) -> Option<String> {
+        Some(s.to_owned())
+    }
+
+    #[test]
+    fn owner_and_repo() {
+        assert_eq!(parse("owner/my.repo"), (None, "owner".to_owned(), "my.repo".to_owned(), None, None));
+        assert_eq!(parse("owner/repo.git@v1.0").3, some("v1.0"));
+    }
+
+    #[test]
+    fn github_urls_name_github() {
+        for spec in &["https://github.com/owner/repo", "https://www.github.com/owner/repo.git/",
+        "git@github.com:owner/repo.git", "ssh://git@github.com:22/owner/repo.git", "github.com/owner/repo"] {
+            let (web_url, owner, repo, _, _) = parse(spec);
+            assert_eq!((web_url, owner, repo), (some("https://github.com"), "owner".to_owned(), "repo".to_owned()));
+        }
+    }
+
+    #[test]
+    fn enterprise_hosts() {
+        assert_eq!(parse("git@ghe.example.com:team/tool.git").0, some("https://ghe.example.com"));
+        assert_eq!(parse("http://localhost:8080/team/tool").0, some("http://localhost:8080"));
+    }
+
+    #[test]
+    fn tree_and_blob_links() {
+        assert_eq!(parse("https://github.com/owner/repo/tree/develop/docs/api"),
+        (some("https://github.com"), "owner".to_owned(), "repo".to_owned(), some("develop"), some("docs/api")));
+        let (_, _, _, reference, path) = parse("https://github.com/owner/repo/blob/main/README.md");
+        assert_eq!((reference, path), (some("main"), some("README.md")));
+        assert_eq!(parse("https://github.com/owner/repo/tree/main").4, None);
+    }
+
+    #[test]
+    fn invalid_specs() {
+        for spec in &["my.repo", "owner", "owner/", "ftp://host/owner/repo", "owner/repo/issues/1", "owner/repo@",
+        "-owner/repo", "owner/re po", "owner/..", "https://github.com", "owner/repo@v1/tree/main"] {
+            assert!(Remote::parse(spec).is_err(), "{} should be invalid", spec);
+        }
+    }
+}
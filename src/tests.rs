@@ -0,0 +1,543 @@
+// End-to-end checks of clone, commit, push and pull against a small in-process
+// stand-in for the GitHub git data API and raw file host, which only serves the
+// endpoints they use, followed by checks of what is accepted from GitHub and the
+// command line.
+use super::*;
+use rustc_serialize::base64::FromBase64;
+use std::io::{BufRead, BufReader};
+use std::net::{TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+#[derive(Default)]
+struct Repo {
+    head: String,
+    trees: BTreeMap<String, BTreeMap<String, String>>,
+    commits: BTreeMap<String, (String, String)>,
+    blobs: BTreeMap<String, String>,
+    // Directories handed out as trees of their own, by SHA: (root tree, path).
+    subtrees: BTreeMap<String, (String, String)>,
+    // Recursive listings come back truncated, as for very big repositories.
+    truncate: bool,
+    // Files the raw host fails to serve, and the ones it has served.
+    broken: Vec<String>,
+    served: Vec<String>,
+    next: u32,
+}
+
+impl Repo {
+    fn new_sha(&mut self) -> String {
+        self.next += 1;
+        format!("{:040x}", self.next)
+    }
+    fn add_commit(&mut self, files: &[(&str, &str)], parent: &str) -> String {
+        let tree = self.new_sha();
+        let blobs = files.iter().map(|&(p, c)| (p.to_owned(), git_blob_sha(c.as_bytes()))).collect();
+        self.trees.insert(tree.clone(), blobs);
+        for &(_, content) in files {
+            self.blobs.insert(git_blob_sha(content.as_bytes()), content.to_owned());
+        }
+        let commit = self.new_sha();
+        self.commits.insert(commit.clone(), (tree, parent.to_owned()));
+        commit
+    }
+    fn head_files(&self) -> BTreeMap<String, String> {
+        self.trees[&self.commits[&self.head].0].clone()
+    }
+    // List a tree, or one of the directories in it, the way GitHub does.
+    fn list_tree(&mut self, sha: &str, recursive: bool) -> String {
+        let (root, prefix) = self.subtrees.get(sha).cloned().unwrap_or((sha.to_owned(), String::new()));
+        let mut entries = BTreeMap::new();
+        for (path, blob) in self.trees[&root].clone() {
+            if !path.starts_with(&prefix) {
+                continue;
+            }
+            let rel = &path[prefix.len()..];
+            let dirs: Vec<usize> = rel.match_indices('/').map(|(i, _)| i).collect();
+            for &i in dirs.iter().take(if recursive { dirs.len() } else { 1 }) {
+                let dir = format!("{}{}", prefix, &rel[..i]);
+                let sub = match self.subtrees.iter().find(|&(_, v)| *v == (root.clone(), format!("{}/", dir))) {
+                    Some((sub, _)) => sub.clone(),
+                    None => self.new_sha(),
+                };
+                self.subtrees.insert(sub.clone(), (root.clone(), format!("{}/", dir)));
+                entries.insert(rel[..i].to_owned(), ("040000", "tree", sub));
+            }
+            if recursive || dirs.is_empty() {
+                entries.insert(rel.to_owned(), ("100644", "blob", blob));
+            }
+        }
+        let truncated = recursive && self.truncate;
+        let entries: Vec<String> = entries.iter().take(if truncated { 1 } else { entries.len() })
+        .map(|(path, &(mode, kind, ref sha))| {
+            format!("{{\"path\":\"{}\",\"mode\":\"{}\",\"type\":\"{}\",\"sha\":\"{}\"}}", path, mode, kind, sha)
+        }).collect();
+        format!("{{\"sha\":\"{}\",\"tree\":[{}],\"truncated\":{}}}", sha, entries.join(","), truncated)
+    }
+    fn handle(&mut self, method: &str, path: &str, body: &str) -> (u32, String) {
+        if path.starts_with("/raw/o/r/") {
+            let mut parts = path[9..].splitn(2, '/');
+            let (commit, file) = (parts.next().unwrap(), parts.next().unwrap_or(""));
+            let blob = self.commits.get(commit).and_then(|&(ref tree, _)| self.trees[tree].get(file)).cloned();
+            return match blob {
+                Some(ref sha) if !self.broken.iter().any(|b| b == file) => {
+                    self.served.push(file.to_owned());
+                    (200, self.blobs[sha].clone())
+                },
+                _ => (404, "404: Not Found".to_owned()),
+            };
+        }
+        let path = path.trim_left_matches("/repos/o/r/git/");
+        let body = Json::from_str(body).unwrap_or(Json::Null);
+        let str_at = |key: &str| body.find(key).and_then(|v| v.as_string()).unwrap_or("").to_owned();
+        let sha_reply = |sha: &str| format!("{{\"sha\":\"{}\",\"object\":{{\"sha\":\"{}\"}}}}", sha, sha);
+        match (method, path) {
+            ("GET", "ref/heads/master") => (200, sha_reply(&self.head)),
+            ("GET", p) if p.starts_with("commits/") => match self.commits.get(&p[8..]) {
+                Some(&(ref tree, _)) => (200, format!("{{\"tree\":{}}}", sha_reply(tree))),
+                None => (404, "{\"message\":\"Not Found\"}".to_owned()),
+            },
+            ("GET", p) if p.starts_with("trees/") => {
+                let sha = p[6..].split('?').next().unwrap();
+                (200, self.list_tree(sha, p.ends_with("?recursive=1")))
+            },
+            ("POST", "blobs") => {
+                let content = str_at("content").from_base64().unwrap();
+                let sha = git_blob_sha(&content);
+                self.blobs.insert(sha.clone(), String::from_utf8(content).unwrap());
+                (201, sha_reply(&sha))
+            },
+            ("POST", "trees") => {
+                let mut files = self.trees[&str_at("base_tree")].clone();
+                for entry in body.find("tree").and_then(|t| t.as_array()).unwrap() {
+                    let path = get_json_str(entry, "path");
+                    match entry.find("sha").and_then(|s| s.as_string()) {
+                        Some(sha) => files.insert(path, sha.to_owned()),
+                        None => files.remove(&path),
+                    };
+                }
+                let sha = self.new_sha();
+                self.trees.insert(sha.clone(), files);
+                (201, sha_reply(&sha))
+            },
+            ("POST", "commits") => {
+                let parent = body.find("parents").and_then(|p| p[0].as_string()).unwrap().to_owned();
+                let sha = self.new_sha();
+                self.commits.insert(sha.clone(), (str_at("tree"), parent));
+                (201, sha_reply(&sha))
+            },
+            ("PATCH", "refs/heads/master") => {
+                let sha = str_at("sha");
+                let force = body.find("force").and_then(|f| f.as_boolean()).unwrap_or(false);
+                if !force && self.commits[&sha].1 != self.head {
+                    return (422, "{\"message\":\"Update is not a fast forward\"}".to_owned());
+                }
+                self.head = sha.clone();
+                (200, sha_reply(&sha))
+            },
+            _ => (404, "{\"message\":\"Not Found\"}".to_owned()),
+        }
+    }
+}
+
+fn serve(stream: TcpStream, repo: &Arc<Mutex<Repo>>) {
+    let mut reader = BufReader::new(stream);
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let parts: Vec<String> = line.split_whitespace().map(|s| s.to_owned()).collect();
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        reader.read_line(&mut header).unwrap();
+        if header.trim().is_empty() {
+            break;
+        }
+        if header.to_lowercase().starts_with("content-length:") {
+            length = header[15..].trim().parse().unwrap();
+        }
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body).unwrap();
+    let (code, reply) = repo.lock().unwrap().handle(&parts[0], &parts[1], &String::from_utf8_lossy(&body));
+    let mut stream = reader.into_inner();
+    write!(stream, "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\
+    Connection: close\r\n\r\n{}", code, reply.len(), reply).unwrap();
+}
+
+// Start the mock with one commit holding README.md and clone it by hand into a
+// fresh working copy.
+fn setup(name: &str) -> (GitHub, Client, Project, String, Arc<Mutex<Repo>>) {
+    let repo = Arc::new(Mutex::new(Repo::default()));
+    let head = repo.lock().unwrap().add_commit(&[("README.md", "hello\n")], "");
+    repo.lock().unwrap().head = head.clone();
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let shared = repo.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            serve(stream.unwrap(), &shared);
+        }
+    });
+
+    let wd = env::temp_dir().join(format!("ghwcli-test-{}-{}", std::process::id(), name));
+    let wd = wd.to_string_lossy().into_owned();
+    let _ = fs::remove_dir_all(&wd);
+    write_working_file(&wd, "README.md", b"hello\n").unwrap();
+    let mut meta = Metadata::new("o", "r", "master", &head);
+    meta.add_entry(Entry::new("README.md", &get_content_hash(b"hello\n"), &git_blob_sha(b"hello\n")));
+    write_metadata(&get_meta_dir(&wd), &meta).unwrap();
+
+    // The account signing in is not the repository's owner.
+    let gh: GitHub = json::decode(&format!(
+        "{{\"username\":\"alice\",\"token\":\"t\",\"api_url\":\"http://127.0.0.1:{0}\",\
+        \"raw_url\":\"http://127.0.0.1:{0}/raw\"}}", port)).unwrap();
+    let mut client = Client::new(&gh);
+    client.set_retries(0);
+    client.set_no_proxy(Some("127.0.0.1".to_owned()));
+    let mut project = Project::new("r", "master");
+    project.set_owner("o");
+    (gh, client, project, wd, repo)
+}
+
+fn commit_edits(gh: &GitHub, client: &Client, project: &Project, wd: &str) {
+    write_working_file(wd, "README.md", b"hello, world\n").unwrap();
+    write_working_file(wd, "docs/new.md", b"new\n").unwrap();
+    commit_changes(gh, client, project, wd, "Update readme", false).unwrap();
+}
+
+#[test]
+fn commit_then_push_updates_the_branch() {
+    let (gh, client, project, wd, repo) = setup("push");
+    let before = repo.lock().unwrap().head.clone();
+    commit_edits(&gh, &client, &project, &wd);
+    assert_eq!(repo.lock().unwrap().head, before);
+    assert_eq!(load_pending_commits(&get_meta_dir(&wd)).unwrap().len(), 1);
+
+    push_commits(&gh, &client, &project, &wd, false, false).unwrap();
+    let repo = repo.lock().unwrap();
+    assert_eq!(repo.commits[&repo.head].1, before);
+    let files = repo.head_files();
+    assert_eq!(files["README.md"], git_blob_sha(b"hello, world\n"));
+    assert_eq!(files["docs/new.md"], git_blob_sha(b"new\n"));
+    let meta = load_metadata(&get_meta_dir(&wd)).unwrap();
+    assert_eq!(meta.get_commit(), repo.head);
+    assert!(get_changes(&wd, &meta).unwrap().is_empty());
+    assert!(load_pending_commits(&get_meta_dir(&wd)).unwrap().is_empty());
+}
+
+#[test]
+fn push_refuses_when_the_branch_moved() {
+    let (gh, client, project, wd, repo) = setup("refuse");
+    commit_edits(&gh, &client, &project, &wd);
+    let moved = {
+        let mut repo = repo.lock().unwrap();
+        let parent = repo.head.clone();
+        repo.head = repo.add_commit(&[("README.md", "theirs\n")], &parent);
+        repo.head.clone()
+    };
+    match push_commits(&gh, &client, &project, &wd, false, false) {
+        Err(Error::WorkingCopy(msg)) => assert!(msg.contains("non-fast-forward"), "{}", msg),
+        other => panic!("expected a refusal, got {:?}", other),
+    }
+    assert_eq!(repo.lock().unwrap().head, moved);
+    assert_eq!(load_pending_commits(&get_meta_dir(&wd)).unwrap().len(), 1);
+}
+
+#[test]
+fn force_push_replaces_the_branch() {
+    let (gh, client, project, wd, repo) = setup("force");
+    commit_edits(&gh, &client, &project, &wd);
+    {
+        let mut repo = repo.lock().unwrap();
+        let parent = repo.head.clone();
+        repo.head = repo.add_commit(&[("README.md", "theirs\n")], &parent);
+    }
+    push_commits(&gh, &client, &project, &wd, true, false).unwrap();
+    let repo = repo.lock().unwrap();
+    assert_eq!(repo.head_files()["README.md"], git_blob_sha(b"hello, world\n"));
+    assert_eq!(load_metadata(&get_meta_dir(&wd)).unwrap().get_commit(), repo.head);
+}
+
+// Move the mock's branch on to a new commit holding just the given files.
+fn advance(repo: &Arc<Mutex<Repo>>, files: &[(&str, &str)]) -> String {
+    let mut repo = repo.lock().unwrap();
+    let parent = repo.head.clone();
+    repo.head = repo.add_commit(files, &parent);
+    repo.head.clone()
+}
+
+fn read_working(wd: &str, file: &str) -> String {
+    String::from_utf8(read_file_bytes(&format!("{}/{}", wd, file)).unwrap()).unwrap()
+}
+
+#[test]
+fn pull_takes_changes_made_on_github() {
+    let (gh, client, project, wd, repo) = setup("pull");
+    let head = advance(&repo, &[("README.md", "hello\nmore\n"), ("docs/new.md", "new\n")]);
+    assert!(!pull_changes(&gh, &client, &project, &wd, 4, false).unwrap());
+    assert_eq!(read_working(&wd, "README.md"), "hello\nmore\n");
+    assert_eq!(read_working(&wd, "docs/new.md"), "new\n");
+    let meta = load_metadata(&get_meta_dir(&wd)).unwrap();
+    assert_eq!(meta.get_commit(), head);
+    assert!(get_status(&wd, &meta).unwrap().is_empty());
+}
+
+#[test]
+fn pull_merges_edits_made_on_both_sides() {
+    let (gh, client, project, wd, repo) = setup("merge");
+    advance(&repo, &[("README.md", "one\ntwo\nthree\nfour\nfive\n")]);
+    pull_changes(&gh, &client, &project, &wd, 4, false).unwrap();
+    write_working_file(&wd, "README.md", b"ONE\ntwo\nthree\nfour\nfive\n").unwrap();
+    advance(&repo, &[("README.md", "one\ntwo\nthree\nfour\nFIVE\n")]);
+    assert!(!pull_changes(&gh, &client, &project, &wd, 4, false).unwrap());
+    assert_eq!(read_working(&wd, "README.md"), "ONE\ntwo\nthree\nfour\nFIVE\n");
+    let meta = load_metadata(&get_meta_dir(&wd)).unwrap();
+    assert_eq!(get_status(&wd, &meta).unwrap(), vec![(' ', 'M', "README.md".to_owned())]);
+}
+
+#[test]
+fn pull_conflicts_wait_for_resolve() {
+    let (gh, client, project, wd, repo) = setup("conflict");
+    write_working_file(&wd, "README.md", b"ours\n").unwrap();
+    advance(&repo, &[("README.md", "theirs\n")]);
+    assert!(pull_changes(&gh, &client, &project, &wd, 4, false).unwrap());
+    let text = read_working(&wd, "README.md");
+    assert!(text.contains("<<<<<<< local") && text.contains("ours") && text.contains("theirs"), "{}", text);
+    assert!(commit_changes(&gh, &client, &project, &wd, "Too soon", false).is_err());
+    assert!(resolve_conflicts(&wd, &["README.md".to_owned()], false).is_err());
+
+    write_working_file(&wd, "README.md", b"both\n").unwrap();
+    resolve_conflicts(&wd, &["README.md".to_owned()], false).unwrap();
+    commit_changes(&gh, &client, &project, &wd, "Merge", false).unwrap();
+    push_commits(&gh, &client, &project, &wd, false, false).unwrap();
+    assert_eq!(repo.lock().unwrap().head_files()["README.md"], git_blob_sha(b"both\n"));
+}
+
+#[test]
+fn pull_replays_unpushed_commits() {
+    let (gh, client, project, wd, repo) = setup("replay");
+    commit_edits(&gh, &client, &project, &wd);
+    let pending = load_pending_commits(&get_meta_dir(&wd)).unwrap();
+    assert!(!pull_changes(&gh, &client, &project, &wd, 4, false).unwrap());
+    assert_eq!(load_pending_commits(&get_meta_dir(&wd)).unwrap()[0].get_sha(), pending[0].get_sha());
+
+    let head = advance(&repo, &[("README.md", "hello\n"), ("other.txt", "other\n")]);
+    assert!(!pull_changes(&gh, &client, &project, &wd, 4, false).unwrap());
+    let replayed = load_pending_commits(&get_meta_dir(&wd)).unwrap();
+    assert_eq!(replayed.len(), 1);
+    assert_eq!(replayed[0].get_parent(), head);
+    assert_eq!(replayed[0].get_message(), "Update readme");
+    let meta = load_metadata(&get_meta_dir(&wd)).unwrap();
+    assert!(get_changes(&wd, &meta).unwrap().is_empty());
+
+    push_commits(&gh, &client, &project, &wd, false, false).unwrap();
+    let files = repo.lock().unwrap().head_files();
+    assert_eq!(files["README.md"], git_blob_sha(b"hello, world\n"));
+    assert_eq!(files["docs/new.md"], git_blob_sha(b"new\n"));
+    assert_eq!(files["other.txt"], git_blob_sha(b"other\n"));
+}
+
+#[test]
+fn pull_merges_files_changed_by_a_replayed_commit() {
+    let (gh, client, project, wd, repo) = setup("remerge");
+    advance(&repo, &[("README.md", "one\ntwo\nthree\nfour\nfive\n")]);
+    pull_changes(&gh, &client, &project, &wd, 4, false).unwrap();
+    write_working_file(&wd, "README.md", b"ONE\ntwo\nthree\nfour\nfive\n").unwrap();
+    commit_changes(&gh, &client, &project, &wd, "Shout", false).unwrap();
+    advance(&repo, &[("README.md", "one\ntwo\nthree\nfour\nFIVE\n")]);
+    assert!(!pull_changes(&gh, &client, &project, &wd, 4, false).unwrap());
+    let merged = "ONE\ntwo\nthree\nfour\nFIVE\n";
+    assert_eq!(read_working(&wd, "README.md"), merged);
+    let meta = load_metadata(&get_meta_dir(&wd)).unwrap();
+    assert!(get_changes(&wd, &meta).unwrap().is_empty());
+    push_commits(&gh, &client, &project, &wd, false, false).unwrap();
+    assert_eq!(repo.lock().unwrap().head_files()["README.md"], git_blob_sha(merged.as_bytes()));
+}
+
+#[test]
+fn pull_keeps_commits_it_cannot_replay_as_changes() {
+    let (gh, client, project, wd, repo) = setup("fold");
+    commit_edits(&gh, &client, &project, &wd);
+    advance(&repo, &[("README.md", "theirs\n")]);
+    assert!(pull_changes(&gh, &client, &project, &wd, 4, false).unwrap());
+    assert!(load_pending_commits(&get_meta_dir(&wd)).unwrap().is_empty());
+    assert!(read_working(&wd, "README.md").contains("hello, world"));
+    assert_eq!(read_working(&wd, "docs/new.md"), "new\n");
+}
+
+// Move the mock's branch to a commit with nested directories and pick an empty
+// directory to clone it into.
+fn setup_clone(name: &str) -> (GitHub, Client, Project, String, Arc<Mutex<Repo>>) {
+    let (gh, client, project, wd, repo) = setup(name);
+    {
+        let mut repo = repo.lock().unwrap();
+        let parent = repo.head.clone();
+        repo.head = repo.add_commit(&[("README.md", "hello\n"), ("docs/guide.md", "guide\n"),
+        ("src/deep/a/b.txt", "deep\n")], &parent);
+    }
+    let wd = format!("{}-clone", wd);
+    let _ = fs::remove_dir_all(&wd);
+    (gh, client, project, wd, repo)
+}
+
+fn clone(gh: &GitHub, client: &Client, project: &Project, wd: &str, depth: u32, keep_going: bool)
+-> Result<(), Error> {
+    retrieve_repo(gh, client, project, wd, None, &Filter::new("", vec![], vec![]), depth, 4, keep_going, false)
+}
+
+#[test]
+fn clone_writes_nested_directories() {
+    let (gh, client, project, wd, repo) = setup_clone("clone");
+    clone(&gh, &client, &project, &wd, 0, false).unwrap();
+    assert_eq!(read_file_bytes(&format!("{}/src/deep/a/b.txt", wd)).unwrap(), b"deep\n");
+    assert_eq!(read_file_bytes(&format!("{}/docs/guide.md", wd)).unwrap(), b"guide\n");
+    let meta = load_metadata(&get_meta_dir(&wd)).unwrap();
+    assert_eq!(meta.get_commit(), repo.lock().unwrap().head);
+    assert_eq!(meta.get_entries().len(), 3);
+    assert!(get_changes(&wd, &meta).unwrap().is_empty());
+}
+
+#[test]
+fn clone_stops_at_the_given_depth() {
+    let (gh, client, project, wd, _) = setup_clone("depth");
+    clone(&gh, &client, &project, &wd, 2, false).unwrap();
+    assert!(Path::new(&format!("{}/docs/guide.md", wd)).exists());
+    assert!(Path::new(&format!("{}/src/deep", wd)).is_dir());
+    assert!(!Path::new(&format!("{}/src/deep/a", wd)).exists());
+    assert_eq!(load_metadata(&get_meta_dir(&wd)).unwrap().get_entries().len(), 2);
+}
+
+#[test]
+fn failed_fetches_fail_the_clone_unless_kept_going() {
+    let (gh, client, project, wd, repo) = setup_clone("broken");
+    repo.lock().unwrap().broken.push("docs/guide.md".to_owned());
+    match clone(&gh, &client, &project, &wd, 0, false) {
+        Err(Error::Http(404, msg)) => assert!(msg.contains("--keep-going"), "{}", msg),
+        other => panic!("expected the clone to fail, got {:?}", other),
+    }
+    let _ = fs::remove_dir_all(&wd);
+    clone(&gh, &client, &project, &wd, 0, true).unwrap();
+    assert!(Path::new(&format!("{}/src/deep/a/b.txt", wd)).exists());
+    assert!(!Path::new(&format!("{}/docs/guide.md", wd)).exists());
+    assert!(load_metadata(&get_meta_dir(&wd)).unwrap().find("docs/guide.md").is_none());
+}
+
+#[test]
+fn cloning_again_only_fetches_changed_files() {
+    let (gh, client, project, wd, repo) = setup_clone("again");
+    clone(&gh, &client, &project, &wd, 0, false).unwrap();
+    {
+        let mut repo = repo.lock().unwrap();
+        let parent = repo.head.clone();
+        repo.head = repo.add_commit(&[("README.md", "hello\n"), ("docs/guide.md", "better guide\n"),
+        ("src/deep/a/b.txt", "deep\n")], &parent);
+        repo.served.clear();
+    }
+    clone(&gh, &client, &project, &wd, 0, false).unwrap();
+    assert_eq!(repo.lock().unwrap().served, vec!["docs/guide.md".to_owned()]);
+    assert_eq!(read_file_bytes(&format!("{}/docs/guide.md", wd)).unwrap(), b"better guide\n");
+}
+
+#[test]
+fn truncated_trees_are_walked_a_directory_at_a_time() {
+    let (gh, client, project, wd, repo) = setup_clone("truncated");
+    repo.lock().unwrap().truncate = true;
+    clone(&gh, &client, &project, &wd, 0, false).unwrap();
+    assert_eq!(read_file_bytes(&format!("{}/src/deep/a/b.txt", wd)).unwrap(), b"deep\n");
+    assert_eq!(load_metadata(&get_meta_dir(&wd)).unwrap().get_entries().len(), 3);
+}
+
+#[test]
+fn tree_paths_stay_inside_the_working_copy() {
+    for path in &["README.md", "docs/a b/c.md", ".hidden/x", "..data"] {
+        assert!(is_safe_path(path), "{} should be allowed", path);
+    }
+    for path in &["", "/etc/passwd", "../x", "docs/../../x", "a//b", "./a", "a/", "a\\..\\b"] {
+        assert!(!is_safe_path(path), "{} should be refused", path);
+    }
+}
+
+#[test]
+fn response_shas_must_be_hex() {
+    let sha = "0123456789abcdef0123456789abcdef01234567";
+    let res = Json::from_str(&format!("{{\"sha\":\"{}\"}}", sha)).unwrap();
+    assert_eq!(get_response_sha(&res, &["sha"], "test", 200).unwrap(), sha);
+    for bad in &["\"../../x\"", "\"abc\"", "\"\"", "null"] {
+        let res = Json::from_str(&format!("{{\"sha\":{}}}", bad)).unwrap();
+        assert!(get_response_sha(&res, &["sha"], "test", 200).is_err(), "{} should be refused", bad);
+    }
+}
+
+#[test]
+fn hosts_in_specs_must_match_the_account() {
+    assert!(check_spec_host("https://github.com/o/r", "https://github.com", "github.com").is_ok());
+    assert!(check_spec_host("https://evil.example/o/r", "https://evil.example", "github.com").is_err());
+    assert!(check_spec_host("git@github.com:o/r", "https://github.com", "ghe.example.com").is_err());
+}
+
+#[test]
+fn owners_stay_apart_from_the_login() {
+    let dir = env::temp_dir().join(format!("ghwcli-test-{}-owner", std::process::id()));
+    let dir = dir.to_string_lossy().into_owned();
+    let (ghconf, prjconf) = (format!("{}/.github.json", dir), format!("{}/.project.json", dir));
+    let none = (String::new(), Json::Object(BTreeMap::new()));
+    write_working_file(&dir, ".github.json", b"{\"username\":\"alice\",\"credential_helper\":\"store\"}").unwrap();
+    write_working_file(&dir, ".project.json", b"{\"name\":\"tool\",\"branch\":\"main\"}").unwrap();
+    let config = load_configuration(&ghconf, &prjconf, &none, &Json::Object(BTreeMap::new())).unwrap();
+    assert_eq!(load_project_configuration(&config).unwrap().get_full_name(), "alice/tool");
+
+    let flags = Json::from_str("{\"owner\":\"someorg\",\"name\":\"repo\",\"branch\":\"dev\"}").unwrap();
+    let config = load_configuration(&ghconf, &prjconf, &none, &flags).unwrap();
+    assert_eq!(load_gh_configuration(&config).unwrap().get_username(), "alice");
+    assert_eq!(load_project_configuration(&config).unwrap().get_full_name(), "someorg/repo");
+}
+
+// Answer one request on a fresh port and hand back the headers it came with.
+fn answer_once(reply: String) -> (u16, thread::JoinHandle<Vec<String>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let handle = thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut headers = Vec::new();
+        loop {
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            if header.trim().is_empty() {
+                break;
+            }
+            headers.push(header.trim().to_lowercase());
+        }
+        reader.into_inner().write_all(reply.as_bytes()).unwrap();
+        headers
+    });
+    (port, handle)
+}
+
+#[test]
+fn redirects_leave_credentials_behind() {
+    let (to, moved) = answer_once(
+        "HTTP/1.1 200 Mock\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}".to_owned());
+    let (from, renamed) = answer_once(format!("HTTP/1.1 301 Mock\r\nLocation: http://localhost:{}/repos/o/r\r\n\
+    Content-Length: 0\r\nConnection: close\r\n\r\n", to));
+    let gh: GitHub = json::decode(&format!(
+        "{{\"username\":\"alice\",\"token\":\"t\",\"api_url\":\"http://127.0.0.1:{}\"}}", from)).unwrap();
+    let mut client = Client::new(&gh);
+    client.set_retries(0);
+    client.set_no_proxy(Some("127.0.0.1,localhost".to_owned()));
+    let (code, _) = client.request("GET", &format!("http://127.0.0.1:{}/repos/o/old", from), "").unwrap();
+    assert_eq!(code, 200);
+    assert!(renamed.join().unwrap().iter().any(|h| h.starts_with("authorization:")));
+    assert!(!moved.join().unwrap().iter().any(|h| h.starts_with("authorization:")));
+}
+
+#[test]
+fn file_urls_escape_odd_names() {
+    let gh: GitHub = json::decode("{\"username\":\"alice\",\"raw_url\":\"https://raw.example.com/\"}").unwrap();
+    let mut project = Project::new("r", "master");
+    project.set_owner("o");
+    assert_eq!(get_file_url(&gh, &project, "abc123", "docs/a b#1?.md"),
+    "https://raw.example.com/o/r/abc123/docs/a%20b%231%3F.md");
+    assert_eq!(get_file_url(&gh, &project, "abc123", "100%/ünï.txt"),
+    "https://raw.example.com/o/r/abc123/100%25/%C3%BCn%C3%AF.txt");
+}
@@ -0,0 +1,322 @@
+use std::cmp;
+use std::collections::BTreeMap;
+
+const CONTEXT: usize = 3;
+
+fn split_lines(text: &str) -> Vec<&str> {
+    if text.is_empty() {
+        return Vec::new();
+    }
+    let body = if text.ends_with("\n") { &text[..text.len() - 1] } else { text };
+    body.split("\n").collect()
+}
+
+/// Rough number of line comparisons to spend on two texts; stretches that would
+/// take more count as rewritten instead of being searched for what they share.
+const MAX_WORK: usize = 200_000_000;
+
+/// Map each line of orig to the line of edit it was kept as, using Myers' linear
+/// space diff, so even long files with edits all over them are cheap to compare.
+pub fn match_lines(orig: &[&str], edit: &[&str]) -> BTreeMap<usize, usize> {
+    let mut matches = BTreeMap::new();
+    let limit = cmp::max(1000, MAX_WORK / cmp::max(1, orig.len() + edit.len()));
+    match_region(orig, edit, 0, 0, limit, &mut matches);
+    matches
+}
+
+fn match_region(a: &[&str], b: &[&str], ai: usize, bi: usize, limit: usize, matches: &mut BTreeMap<usize, usize>) {
+    let mut start = 0;
+    while start < a.len() && start < b.len() && a[start] == b[start] {
+        matches.insert(ai + start, bi + start);
+        start += 1;
+    }
+    let mut end = 0;
+    while end < a.len() - start && end < b.len() - start && a[a.len() - 1 - end] == b[b.len() - 1 - end] {
+        matches.insert(ai + a.len() - 1 - end, bi + b.len() - 1 - end);
+        end += 1;
+    }
+    let (a, b) = (&a[start..a.len() - end], &b[start..b.len() - end]);
+    let (ai, bi) = (ai + start, bi + start);
+    if a.is_empty() || b.is_empty() {
+        return;
+    }
+    // Both sides now differ at each end, so the snake splits them into two
+    // smaller problems; without one the stretch is simply replaced.
+    if let Some((x0, y0, x1, y1)) = find_middle_snake(a, b, limit) {
+        match_region(&a[..x0], &b[..y0], ai, bi, limit, matches);
+        for k in 0..x1 - x0 {
+            matches.insert(ai + x0 + k, bi + y0 + k);
+        }
+        match_region(&a[x1..], &b[y1..], ai + x1, bi + y1, limit, matches);
+    }
+}
+
+// Run the search from both corners until the paths meet, and give back the
+// stretch of matching lines where they did as (x0, y0, x1, y1).
+fn find_middle_snake(a: &[&str], b: &[&str], limit: usize) -> Option<(usize, usize, usize, usize)> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    let max = cmp::min(((n + m + 1) / 2) as usize, limit) as isize;
+    let offset = max + 1;
+    // Furthest x reached on each diagonal k = x - y, and from the end backwards.
+    let mut forward = vec![0isize; (2 * max + 3) as usize];
+    let mut backward = vec![0isize; (2 * max + 3) as usize];
+    let at = |k: isize| (k + offset) as usize;
+    for d in 0..max + 1 {
+        let mut k = -d;
+        while k <= d {
+            let mut x = if k == -d || (k != d && forward[at(k - 1)] < forward[at(k + 1)]) {
+                forward[at(k + 1)]
+            } else {
+                forward[at(k - 1)] + 1
+            };
+            let (x0, y0) = (x, x - k);
+            while x < n && x - k < m && a[x as usize] == b[(x - k) as usize] {
+                x += 1;
+            }
+            forward[at(k)] = x;
+            let r = delta - k;
+            if delta % 2 != 0 && r.abs() < d && x + backward[at(r)] >= n {
+                return Some((x0 as usize, y0 as usize, x as usize, (x - k) as usize));
+            }
+            k += 2;
+        }
+        let mut k = -d;
+        while k <= d {
+            let mut x = if k == -d || (k != d && backward[at(k - 1)] < backward[at(k + 1)]) {
+                backward[at(k + 1)]
+            } else {
+                backward[at(k - 1)] + 1
+            };
+            let (x0, y0) = (x, x - k);
+            while x < n && x - k < m && a[(n - 1 - x) as usize] == b[(m - 1 - x + k) as usize] {
+                x += 1;
+            }
+            backward[at(k)] = x;
+            let f = delta - k;
+            if delta % 2 == 0 && f.abs() <= d && x + forward[at(f)] >= n {
+                return Some(((n - x) as usize, (m - x + k) as usize, (n - x0) as usize, (m - y0) as usize));
+            }
+            k += 2;
+        }
+    }
+    None
+}
+
+fn get_ops(orig: &str, edit: &str) -> Vec<(char, String)> {
+    let old = split_lines(orig);
+    let new = split_lines(edit);
+    let mut ops: Vec<(char, String)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    for (&oi, &nj) in &match_lines(&old, &new) {
+        ops.extend(old[i..oi].iter().map(|l| ('-', l.to_string())));
+        ops.extend(new[j..nj].iter().map(|l| ('+', l.to_string())));
+        ops.push((' ', old[oi].to_owned()));
+        i = oi + 1;
+        j = nj + 1;
+    }
+    ops.extend(old[i..].iter().map(|l| ('-', l.to_string())));
+    ops.extend(new[j..].iter().map(|l| ('+', l.to_string())));
+    // A last line missing its newline on one side cannot be shared context
+    // unless it is the last line, newline or not, on the other side too.
+    let (orig_nl, edit_nl) = (orig.ends_with("\n"), edit.ends_with("\n"));
+    let old_last = ops.iter().rposition(|op| op.0 != '+');
+    let new_last = ops.iter().rposition(|op| op.0 != '-');
+    let split = match (old_last, new_last) {
+        (Some(o), Some(n)) if o == n && orig_nl != edit_nl => Some(o),
+        (Some(o), Some(n)) if o < n && !orig_nl => Some(o),
+        (Some(o), Some(n)) if n < o && !edit_nl => Some(n),
+        _ => None,
+    };
+    if let Some(k) = split {
+        if ops[k].0 == ' ' {
+            let line = ops[k].1.clone();
+            ops[k] = ('-', line.clone());
+            let mut at = k + 1;
+            while at < ops.len() && ops[at].0 == '-' {
+                at += 1;
+            }
+            ops.insert(at, ('+', line));
+        }
+    }
+    ops
+}
+
+fn get_range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+/// Render the changes between orig and edit as a unified diff,
+/// returning an empty string when they are the same.
+pub fn unified_diff(orig: &str, edit: &str, old_name: &str, new_name: &str) -> String {
+    let ops = get_ops(orig, edit);
+    let changes: Vec<usize> = (0..ops.len()).filter(|&i| ops[i].0 != ' ').collect();
+    if changes.is_empty() {
+        return String::new();
+    }
+    let old_total = ops.iter().filter(|op| op.0 != '+').count();
+    let new_total = ops.iter().filter(|op| op.0 != '-').count();
+
+    // Group changes whose context windows touch into hunks.
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for &i in &changes {
+        let start = if i > CONTEXT { i - CONTEXT } else { 0 };
+        let end = if i + CONTEXT + 1 < ops.len() { i + CONTEXT + 1 } else { ops.len() };
+        let merged = match hunks.last_mut() {
+            Some(h) if start <= h.1 => {
+                h.1 = end;
+                true
+            },
+            _ => false,
+        };
+        if !merged {
+            hunks.push((start, end));
+        }
+    }
+
+    let mut out = format!("--- {}\n+++ {}\n", old_name, new_name);
+    for (start, end) in hunks {
+        let old_start = ops[..start].iter().filter(|op| op.0 != '+').count();
+        let new_start = ops[..start].iter().filter(|op| op.0 != '-').count();
+        let old_count = ops[start..end].iter().filter(|op| op.0 != '+').count();
+        let new_count = ops[start..end].iter().filter(|op| op.0 != '-').count();
+        out.push_str(&format!("@@ -{} +{} @@\n",
+        get_range(old_start, old_count), get_range(new_start, new_count)));
+        let (mut o, mut n) = (old_start, new_start);
+        for &(tag, ref line) in &ops[start..end] {
+            out.push_str(&format!("{}{}\n", tag, line));
+            if tag != '+' {
+                o += 1;
+            }
+            if tag != '-' {
+                n += 1;
+            }
+            let eof_old = tag != '+' && o == old_total && !orig.ends_with("\n");
+            let eof_new = tag != '-' && n == new_total && !edit.ends_with("\n");
+            if eof_old || eof_new {
+                out.push_str("\\ No newline at end of file\n");
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{get_ops, match_lines, split_lines, unified_diff};
+
+    #[test]
+    fn first_line_change_has_no_blank_context() {
+        assert_eq!(unified_diff("x\ny\nz\n", "X\ny\nz\n", "a/f", "b/f"),
+        "--- a/f\n+++ b/f\n@@ -1,3 +1,3 @@\n-x\n+X\n y\n z\n");
+    }
+
+    #[test]
+    fn blank_lines_stay_context() {
+        assert_eq!(unified_diff("a\n\nb\n", "A\n\nb\n", "a/f", "b/f"),
+        "--- a/f\n+++ b/f\n@@ -1,3 +1,3 @@\n-a\n+A\n \n b\n");
+    }
+
+    #[test]
+    fn ops_rebuild_both_sides() {
+        let lines = ["", "a", "b"];
+        let mut texts = vec![String::new()];
+        for &x in &lines {
+            texts.push(format!("{}\n", x));
+            for &y in &lines {
+                texts.push(format!("{}\n{}\n", x, y));
+                texts.push(format!("{}\n{}", x, y));
+                for &z in &lines {
+                    texts.push(format!("{}\n{}\n{}\n", x, y, z));
+                }
+            }
+        }
+        for orig in &texts {
+            for edit in &texts {
+                let ops = get_ops(orig, edit);
+                let old: Vec<&str> = ops.iter().filter(|op| op.0 != '+').map(|op| &op.1[..]).collect();
+                let new: Vec<&str> = ops.iter().filter(|op| op.0 != '-').map(|op| &op.1[..]).collect();
+                assert_eq!(old, split_lines(orig), "{:?} -> {:?}", orig, edit);
+                assert_eq!(new, split_lines(edit), "{:?} -> {:?}", orig, edit);
+            }
+        }
+    }
+
+    // Every sequence over a two-line alphabet up to six lines long against every
+    // other, checked against the plain quadratic longest common subsequence.
+    #[test]
+    fn matches_are_a_longest_common_subsequence() {
+        let mut seqs: Vec<Vec<&str>> = vec![Vec::new()];
+        for len in 1..7 {
+            for bits in 0..1 << len {
+                seqs.push((0..len).map(|i| if bits >> i & 1 == 1 { "a" } else { "b" }).collect());
+            }
+        }
+        for a in &seqs {
+            for b in &seqs {
+                let mut table = vec![vec![0; b.len() + 1]; a.len() + 1];
+                for i in 0..a.len() {
+                    for j in 0..b.len() {
+                        table[i + 1][j + 1] = if a[i] == b[j] {
+                            table[i][j] + 1
+                        } else {
+                            table[i][j + 1].max(table[i + 1][j])
+                        };
+                    }
+                }
+                let matches = match_lines(a, b);
+                assert_eq!(matches.len(), table[a.len()][b.len()], "{:?} {:?}", a, b);
+                let mut last = None;
+                for (&i, &j) in &matches {
+                    assert_eq!(a[i], b[j]);
+                    assert!(last.map(|(li, lj)| li < i && lj < j).unwrap_or(true));
+                    last = Some((i, j));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn long_files_are_cheap() {
+        let orig: Vec<String> = (0..50000).map(|i| format!("line {}", i)).collect();
+        let mut edit = orig.clone();
+        for i in (0..50000).step_by(997) {
+            edit[i] = format!("changed {}", i);
+        }
+        let (orig, edit): (Vec<&str>, Vec<&str>) = (orig.iter().map(|l| &l[..]).collect(),
+        edit.iter().map(|l| &l[..]).collect());
+        assert_eq!(match_lines(&orig, &edit).len(), 50000 - 51);
+        // Nothing in common: given up on early rather than searched to the end.
+        let other: Vec<String> = (0..50000).map(|i| format!("other {}", i)).collect();
+        let other: Vec<&str> = other.iter().map(|l| &l[..]).collect();
+        assert!(match_lines(&orig, &other).is_empty());
+    }
+
+    #[test]
+    fn appending_after_missing_newline() {
+        assert_eq!(unified_diff("a", "a\nb", "a/f", "b/f"),
+        concat!("--- a/f\n+++ b/f\n@@ -1 +1,2 @@\n-a\n\\ No newline at end of file\n",
+        "+a\n+b\n\\ No newline at end of file\n"));
+    }
+
+    #[test]
+    fn removing_after_missing_newline() {
+        assert_eq!(unified_diff("a\nb\n", "a", "a/f", "b/f"),
+        "--- a/f\n+++ b/f\n@@ -1,2 +1 @@\n-a\n-b\n+a\n\\ No newline at end of file\n");
+    }
+
+    #[test]
+    fn only_final_newline_differs() {
+        assert_eq!(unified_diff("a\n", "a", "a/f", "b/f"),
+        "--- a/f\n+++ b/f\n@@ -1 +1 @@\n-a\n+a\n\\ No newline at end of file\n");
+    }
+
+    #[test]
+    fn same_content_gives_nothing() {
+        assert_eq!(unified_diff("a\nb\n", "a\nb\n", "a/f", "b/f"), "");
+    }
+}